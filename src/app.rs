@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 use std::{io::stdout, time::Instant};
 use std::{sync::mpsc, time::Duration};
@@ -8,17 +10,32 @@ use ratatui::crossterm::{
 };
 use ratatui::{prelude::*, widgets::*};
 
-use crate::config::{Config, ViewType};
-use crate::metrics::{zero_div, Metrics, Sampler};
+use crate::config::{clamp_interval, Config, StatsType, ViewType};
+use crate::graphics::{self, Plot, Protocol};
+use crate::metrics::{
+  flat_fields, get_boot_power, session_power, zero_div, EnergyCounter, Metrics, MetricsFilter,
+  PowerPart, PowerSampler, Sampler, Stats,
+};
+use crate::watch::{desktop_notify, Alert};
 use crate::{
-  metrics::{MemMetrics, TempMetrics},
-  sources::SocInfo,
+  metrics::{MemBreakdown, MemMetrics, MemRates, TempMetrics},
+  remote::fetch_metrics,
+  sources::{is_display_asleep, watch_display_sleep, SocInfo},
 };
 
 type WithError<T> = Result<T, Box<dyn std::error::Error>>;
 
 const GB: u64 = 1024 * 1024 * 1024;
 const MAX_SPARKLINE: usize = 128;
+const INSTANT_POWER_MSEC: u32 = 100;
+const DISPLAY_SLEEP_MSEC: u32 = 5000;
+const COMPACT_MIN_WIDTH: u16 = 80;
+const COMPACT_MIN_HEIGHT: u16 = 18;
+const NOTICE_MSEC: u128 = 3000;
+const LOG_FLOOR_MW: f64 = 100.0;
+const SCROLL_STEP: isize = 10;
+const MIN_REFRESH: u64 = 50; // ms, redraw tick limits
+const MAX_REFRESH: u64 = 1000;
 
 // MARK: Term utils
 
@@ -41,11 +58,19 @@ fn leave_term() {
   stdout().execute(terminal::LeaveAlternateScreen).unwrap();
 }
 
+// size from resize event, so layout does not wait for next size query; also clears screen
+fn resize_term(term: &mut Terminal<impl Backend>, w: u16, h: u16) {
+  let _ = term.resize(Rect::new(0, 0, w, h));
+}
+
 // MARK: Storage
 
+// samples kept in each chart history, from `history_size` config, same for all stores
+static HISTORY_SIZE: AtomicUsize = AtomicUsize::new(MAX_SPARKLINE);
+
 fn items_add<T>(vec: &mut Vec<T>, val: T) -> &Vec<T> {
   vec.insert(0, val);
-  if vec.len() > MAX_SPARKLINE {
+  if vec.len() > HISTORY_SIZE.load(Ordering::Relaxed) {
     vec.pop();
   }
   vec
@@ -54,15 +79,27 @@ fn items_add<T>(vec: &mut Vec<T>, val: T) -> &Vec<T> {
 #[derive(Debug, Default)]
 struct FreqStore {
   items: Vec<u64>, // from 0 to 100
+  recent: Vec<(u64, f64)>,
   top_value: u64,
   usage: f64, // from 0.0 to 1.0
 }
 
 impl FreqStore {
-  fn push(&mut self, value: u64, usage: f64) {
+  // displayed values are averaged over last `window` samples, chart keeps raw ones
+  fn push(&mut self, value: u64, usage: f64, window: usize) {
     items_add(&mut self.items, (usage * 100.0) as u64);
-    self.top_value = value;
-    self.usage = usage;
+    self.recent.insert(0, (value, usage));
+    self.recent.truncate(window.max(1));
+
+    let count = self.recent.len();
+    self.top_value = self.recent.iter().map(|x| x.0).sum::<u64>() / count as u64;
+    self.usage = self.recent.iter().map(|x| x.1).sum::<f64>() / count as f64;
+  }
+
+  // history is dropped, current value is kept until next sample
+  fn reset(&mut self) {
+    self.items.clear();
+    self.recent.clear();
   }
 }
 
@@ -70,17 +107,52 @@ impl FreqStore {
 struct PowerStore {
   items: Vec<u64>,
   top_value: f64,
-  max_value: f64,
-  avg_value: f64,
+  delta: f64, // change of raw value vs previous sample
+  stats: Stats,
 }
 
 impl PowerStore {
-  fn push(&mut self, value: f64) {
-    let was_top = if self.items.len() > 0 { self.items[0] as f64 / 1000.0 } else { 0.0 };
+  // displayed value is averaged over last `window` samples, chart keeps raw ones
+  fn push(&mut self, value: f64, window: usize) {
     items_add(&mut self.items, (value * 1000.0) as u64);
-    self.top_value = avg2(was_top, value);
-    self.avg_value = self.items.iter().sum::<u64>() as f64 / self.items.len() as f64 / 1000.0;
-    self.max_value = self.items.iter().max().map_or(0, |v| *v) as f64 / 1000.0;
+    self.delta = match self.items.get(1) {
+      Some(prev) => (self.items[0] as f64 - *prev as f64) / 1000.0,
+      None => 0.0,
+    };
+
+    let recent = &self.items[..window.clamp(1, self.items.len())];
+    self.top_value = recent.iter().sum::<u64>() as f64 / recent.len() as f64 / 1000.0;
+    let items = self.items.iter().take(MAX_SPARKLINE); // stats window does not depend on history
+    self.stats = Stats::new(&items.map(|x| *x as f32 / 1000.0).collect::<Vec<_>>());
+  }
+
+  // update only current value, history updated on full interval
+  fn set_instant(&mut self, value: f64) {
+    self.top_value = value;
+  }
+
+  fn reset(&mut self) {
+    self.items.clear();
+    self.delta = 0.0;
+    self.stats = Stats::default();
+  }
+}
+
+#[derive(Debug, Default)]
+struct TempStore {
+  cpu_items: Vec<u64>, // 0.1°C
+  gpu_items: Vec<u64>, // 0.1°C
+}
+
+impl TempStore {
+  fn push(&mut self, value: &TempMetrics) {
+    items_add(&mut self.cpu_items, (value.cpu_temp_avg * 10.0) as u64);
+    items_add(&mut self.gpu_items, (value.gpu_temp_avg * 10.0) as u64);
+  }
+
+  fn reset(&mut self) {
+    self.cpu_items.clear();
+    self.gpu_items.clear();
   }
 }
 
@@ -92,6 +164,9 @@ struct MemoryStore {
   swap_usage: u64,
   swap_total: u64,
   max_ram: u64,
+  pressure: u8,
+  breakdown: MemBreakdown,
+  rates: MemRates,
 }
 
 impl MemoryStore {
@@ -102,6 +177,14 @@ impl MemoryStore {
     self.swap_usage = value.swap_usage;
     self.swap_total = value.swap_total;
     self.max_ram = self.items.iter().max().map_or(0, |v| *v);
+    self.pressure = value.mem_pressure;
+    self.breakdown = value.breakdown;
+    self.rates = value.rates;
+  }
+
+  fn reset(&mut self) {
+    self.items.clear();
+    self.max_ram = 0;
   }
 }
 
@@ -116,24 +199,127 @@ fn h_stack(area: Rect) -> (Rect, Rect) {
   (ha[0], ha[1])
 }
 
+fn h_tiles(area: Rect, count: usize) -> Vec<Rect> {
+  let constraints = vec![Constraint::Fill(1); count];
+  Layout::default().direction(Direction::Horizontal).constraints(constraints).split(area).to_vec()
+}
+
+// decimals from config, otherwise by magnitude: 1.23, 12.3, 123
+fn format_num(val: f64, precision: Option<usize>) -> String {
+  let auto = match val.abs() {
+    x if x < 10.0 => 2,
+    x if x < 100.0 => 1,
+    _ => 0,
+  };
+
+  format!("{:.*}", precision.unwrap_or(auto), val)
+}
+
+// GHz is easier to scan for high frequencies, both variants have the same width
+fn format_freq(mhz: u64, precision: Option<usize>) -> String {
+  match mhz {
+    x if x >= 1000 => format!("{} GHz", format_num(x as f64 / 1000.0, precision)),
+    x => format!("{:4} MHz", x),
+  }
+}
+
+// replacements of non-ASCII glyphs used in labels and charts, see --ascii
+fn ascii_glyph(c: char) -> Option<&'static str> {
+  match c {
+    '°' => Some(""),
+    '▲' => Some("+"),
+    '▼' => Some("-"),
+    '◀' => Some("<"),
+    '←' => Some("<-"),
+    '→' => Some("->"),
+    '±' => Some("+-"),
+    '–' => Some("-"),
+    '·' => Some("|"),
+    '█' => Some("#"),
+    '▓' => Some("%"),
+    '▒' => Some("="),
+    '░' => Some("."),
+    _ => None,
+  }
+}
+
+const ASCII_BORDER: symbols::border::Set = symbols::border::Set {
+  top_left: "+",
+  top_right: "+",
+  bottom_left: "+",
+  bottom_right: "+",
+  vertical_left: "|",
+  vertical_right: "|",
+  horizontal_top: "-",
+  horizontal_bottom: "-",
+};
+
+const ASCII_BARS: symbols::bar::Set = symbols::bar::Set {
+  full: "#",
+  seven_eighths: "#",
+  three_quarters: "#",
+  five_eighths: "=",
+  half: "=",
+  three_eighths: "-",
+  one_quarter: "-",
+  one_eighth: ".",
+  empty: " ",
+};
+
 // MARK: Threads
 
 enum Event {
   Update(Metrics),
+  RemoteUpdate(usize, Metrics),
+  RemoteError(usize, String),
+  InstantPower(Metrics),
+  Error(String),
+  DisplaySleep(bool),
+  Redraw(u16, u16), // terminal resized to (cols, rows)
   ChangeColor,
   ChangeView,
+  ChangeStats,
+  NextProfile,
+  ToggleHelp,
+  Reset,
+  Scroll(isize), // samples back in history, positive – older
+  ScrollLive,
+  ToggleInstant,
+  ToggleDeltas,
+  Snapshot,
   IncInterval,
   DecInterval,
   Tick,
+  Panic,
   Quit,
 }
 
+// panic in background thread is reported to main loop, so it can restore terminal and exit
+fn spawn_thread<F: FnOnce() + Send + 'static>(tx: mpsc::Sender<Event>, f: F) {
+  std::thread::spawn(move || {
+    if std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).is_err() {
+      let _ = tx.send(Event::Panic);
+    }
+  });
+}
+
 fn handle_key_event(key: &event::KeyEvent, tx: &mpsc::Sender<Event>) -> WithError<()> {
   match key.code {
     KeyCode::Char('q') => Ok(tx.send(Event::Quit)?),
     KeyCode::Char('c') if key.modifiers == KeyModifiers::CONTROL => Ok(tx.send(Event::Quit)?),
     KeyCode::Char('c') => Ok(tx.send(Event::ChangeColor)?),
     KeyCode::Char('v') => Ok(tx.send(Event::ChangeView)?),
+    KeyCode::Char('p') => Ok(tx.send(Event::ChangeStats)?),
+    KeyCode::Char('P') => Ok(tx.send(Event::NextProfile)?),
+    KeyCode::Char('?') => Ok(tx.send(Event::ToggleHelp)?),
+    KeyCode::Char('r') => Ok(tx.send(Event::Reset)?),
+    KeyCode::Left => Ok(tx.send(Event::Scroll(SCROLL_STEP))?),
+    KeyCode::Right => Ok(tx.send(Event::Scroll(-SCROLL_STEP))?),
+    KeyCode::Home => Ok(tx.send(Event::Scroll(isize::MAX))?),
+    KeyCode::End => Ok(tx.send(Event::ScrollLive)?),
+    KeyCode::Char('i') => Ok(tx.send(Event::ToggleInstant)?),
+    KeyCode::Char('s') => Ok(tx.send(Event::Snapshot)?),
+    KeyCode::Char('d') => Ok(tx.send(Event::ToggleDeltas)?),
     KeyCode::Char('+') => Ok(tx.send(Event::IncInterval)?),
     KeyCode::Char('=') => Ok(tx.send(Event::IncInterval)?), // fallback to press without shift
     KeyCode::Char('-') => Ok(tx.send(Event::DecInterval)?),
@@ -144,13 +330,14 @@ fn handle_key_event(key: &event::KeyEvent, tx: &mpsc::Sender<Event>) -> WithErro
 fn run_inputs_thread(tx: mpsc::Sender<Event>, tick: u64) {
   let tick_rate = Duration::from_millis(tick);
 
-  std::thread::spawn(move || {
+  spawn_thread(tx.clone(), move || {
     let mut last_tick = Instant::now();
 
     loop {
       if event::poll(Duration::from_millis(tick)).unwrap() {
         match event::read().unwrap() {
           event::Event::Key(key) => handle_key_event(&key, &tx).unwrap(),
+          event::Event::Resize(w, h) => tx.send(Event::Redraw(w, h)).unwrap(),
           _ => {}
         };
       }
@@ -163,17 +350,108 @@ fn run_inputs_thread(tx: mpsc::Sender<Event>, tick: u64) {
   });
 }
 
-fn run_sampler_thread(tx: mpsc::Sender<Event>, msec: Arc<RwLock<u32>>) {
-  std::thread::spawn(move || {
-    let mut sampler = Sampler::new().unwrap();
+fn run_sampler_thread(
+  tx: mpsc::Sender<Event>,
+  msec: Arc<RwLock<u32>>,
+  filter: Arc<RwLock<MetricsFilter>>,
+  cfg: &Config,
+) {
+  let (temp_every, measures, temp_source) = (cfg.temp_every, cfg.measures, cfg.temp_source);
+  let power_parts = cfg.all_power.clone();
+  let pkg_temp_key = cfg.pkg_temp_key.clone();
+  let idle_states = cfg.idle_states.clone();
+  let sensor_prefixes = cfg.sensor_prefixes();
+  spawn_thread(tx.clone(), move || {
+    // errors are not fatal here (e.g. SMC can fail during sleep / wake), so just retry later
+    let mut sampler = loop {
+      match Sampler::new() {
+        Ok(x) => break x,
+        Err(e) => tx.send(Event::Error(e.to_string())).unwrap(),
+      }
+
+      std::thread::sleep(Duration::from_millis(*msec.read().unwrap() as u64));
+    };
+
+    sampler.set_temp_every(temp_every);
+    sampler.set_measures(measures);
+    sampler.set_temp_source(temp_source);
+    sampler.set_power_parts(&power_parts);
+    sampler.set_pkg_temp_key(Some(&pkg_temp_key));
+    sampler.set_idle_states(&idle_states);
+    sampler.set_sensor_prefixes(sensor_prefixes);
 
     // Send initial metrics
-    tx.send(Event::Update(sampler.get_metrics(100).unwrap())).unwrap();
+    let mut next_msec = 100;
 
     loop {
-      let msec = *msec.read().unwrap();
-      tx.send(Event::Update(sampler.get_metrics(msec).unwrap())).unwrap();
+      sampler.set_filter(filter.read().unwrap().clone());
+      next_msec = match sampler.get_metrics(next_msec) {
+        Ok(data) => {
+          tx.send(Event::Update(data)).unwrap();
+          *msec.read().unwrap()
+        }
+        Err(e) => {
+          tx.send(Event::Error(e.to_string())).unwrap();
+          std::thread::sleep(Duration::from_millis(*msec.read().unwrap() as u64));
+          100
+        }
+      };
+    }
+  });
+}
+
+// other hosts are polled independently, so one offline host doesn't block others
+fn run_remote_thread(tx: mpsc::Sender<Event>, idx: usize, host: String, msec: u32) {
+  spawn_thread(tx.clone(), move || loop {
+    match fetch_metrics(&host) {
+      Ok(data) => tx.send(Event::RemoteUpdate(idx, data)).unwrap(),
+      Err(e) => tx.send(Event::RemoteError(idx, e.to_string())).unwrap(),
     }
+
+    std::thread::sleep(Duration::from_millis(msec as u64));
+  });
+}
+
+fn run_instant_power_thread(tx: mpsc::Sender<Event>, enabled: Arc<RwLock<bool>>, cfg: &Config) {
+  let power_parts = cfg.all_power.clone();
+  spawn_thread(tx.clone(), move || {
+    let mut sampler = match PowerSampler::new() {
+      Ok(x) => x,
+      Err(e) => return tx.send(Event::Error(e.to_string())).unwrap(),
+    };
+
+    sampler.set_power_parts(&power_parts);
+
+    loop {
+      if !*enabled.read().unwrap() {
+        std::thread::sleep(Duration::from_millis(INSTANT_POWER_MSEC as u64));
+        continue;
+      }
+
+      match sampler.get_power(INSTANT_POWER_MSEC) {
+        Ok(data) => tx.send(Event::InstantPower(data)).unwrap(),
+        Err(e) => tx.send(Event::Error(e.to_string())).unwrap(),
+      }
+    }
+  });
+}
+
+// reduce own energy usage when nobody looks at the screen
+fn run_display_thread(tx: mpsc::Sender<Event>) {
+  spawn_thread(tx.clone(), move || {
+    let mut was_asleep = is_display_asleep();
+    if was_asleep {
+      tx.send(Event::DisplaySleep(true)).unwrap();
+    }
+
+    // dim and sleep both send power off, so only changes are forwarded; if notifications are
+    // not available (e.g. no display wrangler), sampling just stays at normal speed
+    let _ = watch_display_sleep(move |asleep| {
+      if asleep != was_asleep {
+        let _ = tx.send(Event::DisplaySleep(asleep));
+        was_asleep = asleep;
+      }
+    });
   });
 }
 
@@ -183,6 +461,16 @@ fn avg2<T: num_traits::Float>(a: T, b: T) -> T {
   return if a == T::zero() { b } else { (a + b) / T::from(2.0).unwrap() };
 }
 
+// MARK: Notify
+
+// notification is sent once when condition starts to hold, and not more often than cooldown
+#[derive(Debug)]
+struct NotifyState {
+  alert: Alert,
+  active: bool,
+  fired_at: Option<Instant>,
+}
+
 // MARK: App
 
 #[derive(Debug, Default)]
@@ -192,103 +480,474 @@ pub struct App {
   soc: SocInfo,
   mem: MemoryStore,
   temp: TempMetrics,
+  temps: TempStore,
 
   cpu_power: PowerStore,
   gpu_power: PowerStore,
   ane_power: PowerStore,
   all_power: PowerStore,
   sys_power: PowerStore,
+  ram_power: PowerStore,
+  gpu_ram_power: PowerStore,
 
   ecpu_freq: FreqStore,
   pcpu_freq: FreqStore,
   igpu_freq: FreqStore,
+
+  throttling: bool,
+  sys_power_estimated: bool,
+  error: Option<String>,
+  force_compact: bool,
+  no_color: bool,
+  ascii: bool,
+
+  started_at: Option<Instant>,
+  updated_at: Option<Instant>,
+  samples: u64,
+  energy: EnergyCounter,
+  boot_power: Option<f32>,
+
+  last: Option<Metrics>,
+  notice: Option<(String, Instant)>,
+  show_help: bool,
+  scroll: usize, // samples back from newest shown at right side of charts, 0 – live
+  notify: Vec<NotifyState>,
+
+  // high-res charts drawn after each frame, if supported by terminal
+  graphics: Option<Protocol>,
+  plots: RefCell<Vec<Plot>>,
 }
 
 impl App {
-  pub fn new() -> WithError<Self> {
+  pub fn new(config: Option<&str>) -> WithError<Self> {
     let soc = SocInfo::new()?;
-    let cfg = Config::load();
+    let cfg = Config::load(config);
     Ok(Self { cfg, soc, ..Default::default() })
   }
 
   fn update_metrics(&mut self, data: Metrics) {
-    self.cpu_power.push(data.cpu_power as f64);
-    self.gpu_power.push(data.gpu_power as f64);
-    self.ane_power.push(data.ane_power as f64);
-    self.all_power.push(data.all_power as f64);
-    self.sys_power.push(data.sys_power as f64);
-    self.ecpu_freq.push(data.ecpu_usage.0 as u64, data.ecpu_usage.1 as f64);
-    self.pcpu_freq.push(data.pcpu_usage.0 as u64, data.pcpu_usage.1 as f64);
-    self.igpu_freq.push(data.gpu_usage.0 as u64, data.gpu_usage.1 as f64);
+    self.last = Some(data.clone());
+    let smooth = self.cfg.smoothing;
+    let n = if smooth { self.cfg.smoothing_window } else { 1 };
+    self.cpu_power.push(data.cpu_power as f64, n);
+    self.gpu_power.push(data.gpu_power as f64, n);
+    self.ane_power.push(data.ane_power as f64, n);
+    self.all_power.push(data.all_power as f64, n);
+    self.sys_power.push(data.sys_power as f64, n);
+    self.ram_power.push(data.ram_power as f64, n);
+    self.gpu_ram_power.push(data.gpu_ram_power as f64, n);
+    self.ecpu_freq.push(data.ecpu_usage.0 as u64, data.ecpu_usage.1 as f64, n);
+    self.pcpu_freq.push(data.pcpu_usage.0 as u64, data.pcpu_usage.1 as f64, n);
+    self.igpu_freq.push(data.gpu_usage.0 as u64, data.gpu_usage.1 as f64, n);
+
+    if smooth {
+      self.temp.cpu_temp_avg = avg2(self.temp.cpu_temp_avg, data.temp.cpu_temp_avg);
+      self.temp.gpu_temp_avg = avg2(self.temp.gpu_temp_avg, data.temp.gpu_temp_avg);
+      self.temp.ecpu_temp_avg = avg2(self.temp.ecpu_temp_avg, data.temp.ecpu_temp_avg);
+      self.temp.pcpu_temp_avg = avg2(self.temp.pcpu_temp_avg, data.temp.pcpu_temp_avg);
+      self.temp.pkg_temp = avg2(self.temp.pkg_temp, data.temp.pkg_temp);
+    } else {
+      self.temp = data.temp;
+    }
+
+    self.temps.push(&self.temp);
+    self.mem.push(data.memory.clone());
+    self.throttling = data.throttling;
+    self.sys_power_estimated = data.sys_power_estimated;
+    self.error = None;
+    self.samples += 1;
+
+    // scrolled view stays on the same samples while new ones arrive
+    if self.scroll > 0 {
+      self.scroll = (self.scroll + 1).min(self.max_scroll());
+    }
+
+    let now = Instant::now();
+    let dt = self.updated_at.or(self.started_at).map_or(0, |x| (now - x).as_millis() as u64);
+    self.energy.push(session_power(&data), dt);
+    self.updated_at = Some(now);
+    self.check_notify(&data);
+  }
 
-    self.temp.cpu_temp_avg = avg2(self.temp.cpu_temp_avg, data.temp.cpu_temp_avg);
-    self.temp.gpu_temp_avg = avg2(self.temp.gpu_temp_avg, data.temp.gpu_temp_avg);
+  pub fn add_notify(&mut self, alerts: &[Alert]) {
+    for alert in alerts {
+      self.notify.push(NotifyState { alert: alert.clone(), active: false, fired_at: None });
+    }
+  }
+
+  fn check_notify(&mut self, data: &Metrics) {
+    if self.notify.is_empty() {
+      return;
+    }
+
+    let fields = match serde_json::to_value(data) {
+      Ok(doc) => flat_fields(&doc),
+      Err(_) => return,
+    };
+
+    let cooldown = Duration::from_secs(self.cfg.notify_cooldown);
+    for x in self.notify.iter_mut() {
+      let value = match x.alert.value(&fields) {
+        Ok(value) => value,
+        Err(e) => {
+          self.error = Some(e.to_string());
+          continue;
+        }
+      };
+
+      if !x.alert.check(value) {
+        x.active = false;
+        continue;
+      }
+
+      if x.active || x.fired_at.is_some_and(|t| t.elapsed() < cooldown) {
+        continue;
+      }
+
+      (x.active, x.fired_at) = (true, Some(Instant::now()));
+      desktop_notify("macmon", &format!("{} (current: {:.2})", x.alert.expr(), value));
+    }
+  }
+
+  // new measurement window: history, min / max / avg and percentiles start over
+  fn reset_stats(&mut self) {
+    self.scroll = 0;
+    for x in [
+      &mut self.cpu_power,
+      &mut self.gpu_power,
+      &mut self.ane_power,
+      &mut self.all_power,
+      &mut self.sys_power,
+      &mut self.ram_power,
+      &mut self.gpu_ram_power,
+    ] {
+      x.reset();
+    }
+
+    for x in [&mut self.ecpu_freq, &mut self.pcpu_freq, &mut self.igpu_freq] {
+      x.reset();
+    }
+
+    self.mem.reset();
+    self.temps.reset();
+  }
+
+  // save current readings to home dir, e.g. to attach to an issue
+  fn save_snapshot(&self) -> WithError<String> {
+    let metrics = self.last.as_ref().ok_or("No metrics yet")?;
+    let now = chrono::Utc::now();
+    let doc = serde_json::json!({
+      "timestamp": now.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+      "soc": self.soc,
+      "metrics": metrics,
+    });
+
+    let home = std::env::var("HOME")?;
+    let path = format!("{}/macmon-snapshot-{}.json", home, now.format("%Y%m%d-%H%M%S"));
+    std::fs::write(&path, serde_json::to_string_pretty(&doc)?)?;
+    Ok(path)
+  }
+
+  fn num(&self, val: f64) -> String {
+    format_num(val, self.cfg.precision)
+  }
+
+  // celsius value in configured unit, e.g. "45.2°C"
+  fn temp_label(&self, celsius: f64) -> String {
+    let unit = self.cfg.temp_unit;
+    format!("{}{}", self.num(unit.convert(celsius)), unit.symbol())
+  }
+
+  // from --all-power, overrides config value as --power-max does
+  pub fn set_power_parts(&mut self, parts: &[PowerPart]) {
+    self.cfg.all_power = parts.to_vec();
+  }
+
+  pub fn set_profile(&mut self, name: &str) -> WithError<()> {
+    Ok(self.cfg.apply_profile(name)?)
+  }
+
+  // estimated system power is shown as "~12.34W"
+  fn sys_power_mark(&self) -> &'static str {
+    if self.sys_power_estimated {
+      "~"
+    } else {
+      ""
+    }
+  }
+
+  fn max_scroll(&self) -> usize {
+    self.cpu_power.items.len().saturating_sub(1)
+  }
+
+  fn scroll_by(&mut self, delta: isize) {
+    self.scroll = self.scroll.saturating_add_signed(delta).min(self.max_scroll());
+  }
+
+  // visible part of chart history, newest first
+  fn view<'a>(&self, items: &'a [u64]) -> &'a [u64] {
+    &items[self.scroll.min(items.len())..]
+  }
+
+  // position in history while scrolled, e.g. " ◀ 45 of 1024 samples back (-45s), End – live "
+  fn scroll_label(&self) -> String {
+    let secs = self.scroll as u64 * self.cfg.interval as u64 / 1000;
+    let total = self.cpu_power.items.len();
+    format!(" ◀ {} of {} samples back (-{}s), End – live ", self.scroll, total, secs)
+  }
 
-    self.mem.push(data.memory);
+  fn uptime_label(&self) -> String {
+    let secs = self.started_at.map(|x| x.elapsed().as_secs()).unwrap_or(0);
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    let collect = self.last.as_ref().map_or(0.0, |x| x.collection_ms);
+    let collect = format!("{}ms collect", self.num(collect as f64));
+    format!(" uptime {:02}:{:02}:{:02} · {} samples · {} ", h, m, s, self.samples, collect)
+  }
+
+  fn update_instant_power(&mut self, data: Metrics) {
+    self.cpu_power.set_instant(data.cpu_power as f64);
+    self.gpu_power.set_instant(data.gpu_power as f64);
+    self.ane_power.set_instant(data.ane_power as f64);
+    self.all_power.set_instant(data.all_power as f64);
+    self.ram_power.set_instant(data.ram_power as f64);
+    self.gpu_ram_power.set_instant(data.gpu_ram_power as f64);
+  }
+
+  // config color is kept as is with --no-color, so it's not overwritten on save
+  fn color(&self) -> Color {
+    self.accent(self.cfg.color)
+  }
+
+  // highlight color for warnings, disabled with --no-color
+  fn accent(&self, color: Color) -> Color {
+    if self.no_color {
+      Color::Reset
+    } else {
+      color
+    }
+  }
+
+  fn is_ascii(&self) -> bool {
+    self.ascii || self.cfg.ascii
+  }
+
+  // text with non-ASCII glyphs replaced in ascii mode
+  fn text(&self, s: &str) -> String {
+    match self.is_ascii() {
+      true => s.chars().map(|c| ascii_glyph(c).map_or(c.to_string(), |x| x.to_string())).collect(),
+      false => s.to_string(),
+    }
+  }
+
+  fn glyph(&self, c: char) -> char {
+    self.text(&c.to_string()).chars().next().unwrap_or(c)
+  }
+
+  fn bar_set(&self) -> symbols::bar::Set {
+    if self.is_ascii() {
+      ASCII_BARS
+    } else {
+      symbols::bar::NINE_LEVELS
+    }
   }
 
   fn title_block<'a>(&self, label_l: &str, label_r: &str) -> Block<'a> {
     let mut block = Block::new()
       .borders(Borders::ALL)
       .border_type(BorderType::Rounded)
-      .border_style(self.cfg.color)
+      .border_style(self.color())
       // .title_style(Style::default().gray())
       .padding(Padding::ZERO);
 
+    if self.is_ascii() {
+      block = block.border_set(ASCII_BORDER);
+    }
+
     if label_l.len() > 0 {
-      block = block.title_top(Line::from(format!(" {label_l} ")));
+      block = block.title_top(Line::from(format!(" {} ", self.text(label_l))));
     }
 
     if label_r.len() > 0 {
-      block = block.title_top(Line::from(format!(" {label_r} ")).alignment(Alignment::Right));
+      let line = Line::from(format!(" {} ", self.text(label_r)));
+      block = block.title_top(line.alignment(Alignment::Right));
     }
 
     block
   }
 
-  fn get_power_block<'a>(&self, label: &str, val: &'a PowerStore, temp: f32) -> Sparkline<'a> {
+  // two stats values shown next to the current power value, selected by 'p' key
+  fn power_stats(&self, val: &PowerStore) -> [(&'static str, f32); 2] {
+    match self.cfg.stats_type {
+      StatsType::AvgMax => [("avg", val.stats.avg), ("max", val.stats.max)],
+      StatsType::Percentiles => [("p50", val.stats.p50), ("p95", val.stats.p95)],
+      StatsType::MinMax => [("min", val.stats.min), ("max", val.stats.max)],
+    }
+  }
+
+  // percent of configured power budget, empty if budget not set
+  fn budget_label(&self, watts: f64) -> String {
+    let budget = self.cfg.power_budget as f64;
+    if budget <= 0.0 {
+      return "".to_string();
+    }
+
+    format!(" {:.0}%", watts / budget * 100.0)
+  }
+
+  // change vs previous sample like " ▲0.40", empty if disabled with 'd' key
+  fn delta_label(&self, val: &PowerStore) -> String {
+    if !self.cfg.show_deltas {
+      return "".to_string();
+    }
+
+    match val.delta {
+      x if x.abs() < 0.005 => " ±0".to_string(),
+      x if x > 0.0 => format!(" ▲{}", self.num(x)),
+      x => format!(" ▼{}", self.num(-x)),
+    }
+  }
+
+  fn is_over_budget(&self) -> bool {
+    self.cfg.power_budget > 0.0 && self.all_power.top_value > self.cfg.power_budget as f64
+  }
+
+  // history chart as sparkline, or empty block with high-res plot drawn over it later
+  fn render_chart(&self, f: &mut Frame, r: Rect, block: Block, val: (&[u64], Option<u64>, Color)) {
+    let (items, max, color) = val;
+    let items = self.view(items);
+    if self.graphics.is_some() {
+      let (area, items, max) = (block.inner(r), items.to_vec(), max.unwrap_or(0));
+      self.plots.borrow_mut().push(Plot { area, items, max, color });
+      return f.render_widget(block, r);
+    }
+
+    let w = Sparkline::default()
+      .block(block)
+      .direction(RenderDirection::RightToLeft)
+      .bar_set(self.bar_set())
+      .data(items)
+      .style(color);
+
+    match max {
+      Some(max) => f.render_widget(w.max(max), r),
+      None => f.render_widget(w, r),
+    }
+  }
+
+  // related power not shown as own chart (e.g. DRAM), efficiency and temperature
+  fn power_info(&self, extra: Option<(&str, &PowerStore)>, efficiency: f64, temp: f32) -> String {
+    let mut parts = Vec::new();
+    if let Some((name, x)) = extra.filter(|(_, x)| x.top_value > 0.0) {
+      parts.push(format!("{} {}W", name, self.num(x.top_value)));
+    }
+
+    if efficiency > 0.0 {
+      parts.push(format!("{}%/W", self.num(efficiency * 100.0)));
+    }
+
+    if temp > 0.0 {
+      parts.push(self.temp_label(temp as f64));
+    }
+
+    parts.join(" ")
+  }
+
+  // usage of E + P clusters (or GPU) per Watt from displayed values, same as *_efficiency metrics
+  fn cpu_efficiency(&self) -> f64 {
+    zero_div(self.ecpu_freq.usage + self.pcpu_freq.usage, self.cpu_power.top_value)
+  }
+
+  fn gpu_efficiency(&self) -> f64 {
+    zero_div(self.igpu_freq.usage, self.gpu_power.top_value)
+  }
+
+  fn render_power_block(&self, f: &mut Frame, r: Rect, label: &str, val: &PowerStore, info: &str) {
+    let [a, b] = self.power_stats(val);
     let label_l = format!(
-      "{} {:.2}W ({:.2}, {:.2})",
+      "{} {}W{}{} ({}, {})",
       // "{} {:.2}W (avg: {:.2}W, max: {:.2}W)",
       // "{} {:.2}W (~{:.2}W ^{:.2}W)",
       label,
-      val.top_value,
-      val.avg_value,
-      val.max_value
+      self.num(val.top_value),
+      self.delta_label(val),
+      self.budget_label(val.top_value),
+      self.num(a.1 as f64),
+      self.num(b.1 as f64)
     );
 
-    let label_r = if temp > 0.0 { format!("{:.1}°C", temp) } else { "".to_string() };
+    let block = self.title_block(label_l.as_str(), info);
+    let (items, max) = self.power_chart(&val.items);
+    self.render_chart(f, r, block, (&items, max, self.color()));
+  }
 
-    Sparkline::default()
-      .block(self.title_block(label_l.as_str(), label_r.as_str()))
-      .direction(RenderDirection::RightToLeft)
-      .data(&val.items)
-      .style(self.cfg.color)
+  // fixed sparkline scale in mW, auto-scale to history max if not set
+  fn power_max(&self) -> Option<u64> {
+    match self.cfg.power_max > 0.0 {
+      true => Some((self.cfg.power_max * 1000.0) as u64),
+      false => None,
+    }
   }
 
-  fn render_freq_block(&self, f: &mut Frame, r: Rect, label: &str, val: &FreqStore) {
-    let label = format!("{} {:3.0}% @ {:4.0} MHz", label, val.usage * 100.0, val.top_value);
-    let block = self.title_block(label.as_str(), "");
+  // power history and its max as chart values, log10 of mW over floor when log scale enabled
+  // (0 below 0.1W, 1000 at 1W, 2000 at 10W), so idle changes stay visible next to spikes
+  fn power_chart(&self, items: &[u64]) -> (Vec<u64>, Option<u64>) {
+    if !self.cfg.power_log_scale {
+      return (items.to_vec(), self.power_max());
+    }
+
+    let log = |x: u64| ((x as f64).max(LOG_FLOOR_MW) / LOG_FLOOR_MW).log10() * 1000.0;
+    let items = items.iter().map(|x| log(*x) as u64).collect();
+    (items, self.power_max().map(|x| log(x) as u64))
+  }
+
+  // temp is shown next to frequency when > 0
+  fn render_freq_block(&self, f: &mut Frame, r: Rect, label: &str, val: &FreqStore, temp: f32) {
+    let mut label = format!(
+      "{} {:3.0}% @ {}",
+      label,
+      val.usage * 100.0,
+      format_freq(val.top_value, self.cfg.precision)
+    );
+    if temp > 0.0 {
+      label.push_str(&format!(" {}", self.temp_label(temp as f64)));
+    }
+
+    let (color, label_r) = match self.throttling {
+      true => (self.accent(Color::Yellow), "throttled"),
+      false => (self.color(), ""),
+    };
+
+    let block = self.title_block(label.as_str(), label_r).border_style(color);
 
     match self.cfg.view_type {
-      ViewType::Sparkline => {
-        let w = Sparkline::default()
-          .block(block)
-          .direction(RenderDirection::RightToLeft)
-          .data(&val.items)
-          .max(100)
-          .style(self.cfg.color);
+      ViewType::Sparkline | ViewType::Stacked | ViewType::Full => {
+        self.render_chart(f, r, block, (&val.items, Some(100), color));
+      }
+      ViewType::Gauge => {
+        let w =
+          Gauge::default().block(block).gauge_style(color).style(color).label("").ratio(val.usage);
         f.render_widget(w, r);
       }
+    }
+  }
+
+  // history on 0..100°C scale
+  fn render_temp_block(&self, f: &mut Frame, r: Rect, label: &str, items: &[u64]) {
+    let value = items.first().copied().unwrap_or(0) as f64 / 10.0;
+    let max = items.iter().max().copied().unwrap_or(0) as f64 / 10.0;
+    let label_l = format!("{} {}", label, self.temp_label(value));
+    let label_r = format!("max {}", self.temp_label(max));
+    let block = self.title_block(&label_l, &label_r);
+
+    match self.cfg.view_type {
       ViewType::Gauge => {
-        let w = Gauge::default()
-          .block(block)
-          .gauge_style(self.cfg.color)
-          .style(self.cfg.color)
-          .label("")
-          .ratio(val.usage);
+        let ratio = (value / 100.0).clamp(0.0, 1.0);
+        let color = self.color();
+        let w =
+          Gauge::default().block(block).gauge_style(color).style(color).label("").ratio(ratio);
         f.render_widget(w, r);
       }
+      _ => self.render_chart(f, r, block, (items, Some(1000), self.color())),
     }
   }
 
@@ -299,34 +958,201 @@ impl App {
     let swap_usage_gb = val.swap_usage as f64 / GB as f64;
     let swap_total_gb = val.swap_total as f64 / GB as f64;
 
-    let label_l = format!("RAM {:4.2} / {:4.1} GB", ram_usage_gb, ram_total_gb);
-    let label_r = format!("SWAP {:.2} / {:.1} GB", swap_usage_gb, swap_total_gb);
+    let label_l = format!("RAM {} / {} GB", self.num(ram_usage_gb), self.num(ram_total_gb));
+    let label_r = format!("SWAP {} / {} GB", self.num(swap_usage_gb), self.num(swap_total_gb));
+
+    // kernel memory pressure level: 2 – warn, 4 – critical
+    let color = match val.pressure {
+      2 => self.accent(Color::Yellow),
+      4 => self.accent(Color::Red),
+      _ => self.color(),
+    };
 
-    let block = self.title_block(label_l.as_str(), label_r.as_str());
+    let b = &val.breakdown;
+    let parts = [
+      (self.glyph('█'), "app", b.app_bytes),
+      (self.glyph('▓'), "wired", b.wired_bytes),
+      (self.glyph('▒'), "compr", b.compressed_bytes),
+      (self.glyph('░'), "cached", b.cached_bytes),
+    ];
+
+    let legend = parts
+      .iter()
+      .map(|(c, name, bytes)| format!("{} {} {}", c, name, self.num(*bytes as f64 / GB as f64)))
+      .collect::<Vec<_>>()
+      .join(" ");
+
+    let block = self.title_block(label_l.as_str(), label_r.as_str()).border_style(color);
+    let mut block = block.title_bottom(Line::from(format!(" {} GB ", legend)).right_aligned());
+
+    // churn tells about memory pressure better than static usage, shown only when present
+    let rt = &val.rates;
+    if rt.swapins + rt.swapouts + rt.compressions + rt.decompressions > 0.0 {
+      let rates = format!(
+        " swap in/out {}/{}, compr/decompr {}/{} pages/s ",
+        rt.swapins.round(),
+        rt.swapouts.round(),
+        rt.compressions.round(),
+        rt.decompressions.round()
+      );
+      block = block.title_bottom(Line::from(rates).left_aligned());
+    }
     match self.cfg.view_type {
-      ViewType::Sparkline => {
+      ViewType::Sparkline | ViewType::Stacked | ViewType::Full => {
         let w = Sparkline::default()
           .block(block)
           .direction(RenderDirection::RightToLeft)
-          .data(&val.items)
+          .bar_set(self.bar_set())
+          .data(self.view(&val.items))
           .max(val.ram_total)
-          .style(self.cfg.color);
+          .style(color);
         f.render_widget(w, r);
       }
       ViewType::Gauge => {
-        let w = Gauge::default()
-          .block(block)
-          .gauge_style(self.cfg.color)
-          .style(self.cfg.color)
-          .label("")
-          .ratio(zero_div(ram_usage_gb, ram_total_gb));
+        // stacked bar, each part filled with own char
+        let width = block.inner(r).width as f64;
+        let mut line = String::new();
+        for (c, _, bytes) in parts {
+          let n = (zero_div(bytes as f64, val.ram_total as f64) * width).round() as usize;
+          line.push_str(&c.to_string().repeat(n.min(width as usize - line.chars().count())));
+        }
+
+        let rows = vec![Line::from(line); block.inner(r).height as usize];
+        let w = Paragraph::new(rows).block(block).style(color);
         f.render_widget(w, r);
       }
     }
   }
 
+  // one line per metric group, used for small terminals
+  fn render_compact(&self, f: &mut Frame, r: Rect) {
+    let freq = |label: &str, val: &FreqStore| {
+      format!(
+        "{} {:3.0}% @ {}",
+        label,
+        val.usage * 100.0,
+        format_freq(val.top_value, self.cfg.precision)
+      )
+    };
+
+    let temp = |label: &str, val: f32| {
+      if val > 0.0 {
+        format!(" {} {}", label, self.temp_label(val as f64))
+      } else {
+        "".to_string()
+      }
+    };
+
+    let mut lines = vec![
+      format!("{}  {}", freq("E-CPU", &self.ecpu_freq), freq("P-CPU", &self.pcpu_freq)),
+      freq("GPU", &self.igpu_freq),
+      format!(
+        "RAM {} / {} GB  SWAP {} / {} GB",
+        self.num(self.mem.ram_usage as f64 / GB as f64),
+        self.num(self.mem.ram_total as f64 / GB as f64),
+        self.num(self.mem.swap_usage as f64 / GB as f64),
+        self.num(self.mem.swap_total as f64 / GB as f64),
+      ),
+      format!(
+        "Power {}W  CPU {}W  GPU {}W  ANE {}W  DRAM {}W",
+        self.num(self.all_power.top_value),
+        self.num(self.cpu_power.top_value),
+        self.num(self.gpu_power.top_value),
+        self.num(self.ane_power.top_value),
+        self.num(self.ram_power.top_value),
+      ),
+    ];
+
+    if self.sys_power.top_value > 0.0 {
+      lines.push(format!("Total {}{}W", self.sys_power_mark(), self.num(self.sys_power.top_value)));
+    }
+
+    let temps =
+      format!("{}{}", temp("CPU", self.temp.cpu_temp_avg), temp("GPU", self.temp.gpu_temp_avg));
+    if !temps.is_empty() {
+      lines.push(format!("Temp{}", temps));
+    }
+
+    if let Some(err) = &self.error {
+      lines.push(format!("Error: {}", err));
+    }
+
+    let block = self.title_block(&self.soc.chip_name, "");
+    let lines = lines.into_iter().map(|x| Line::from(self.text(&x))).collect::<Vec<_>>();
+    f.render_widget(Paragraph::new(lines).block(block).style(self.color()), r);
+  }
+
   fn render(&mut self, f: &mut Frame) {
-    let label_l = format!(
+    self.render_main(f);
+    if self.show_help {
+      self.render_help(f);
+    }
+  }
+
+  // legend and keys as centered popup over current view, toggled by '?'
+  fn render_help(&self, f: &mut Frame) {
+    let [a, b] = self.power_stats(&self.all_power);
+    let items = [
+      ("Blocks", ""),
+      ("E-CPU / P-CPU", "usage % @ average frequency of active states"),
+      ("GPU", "same as CPU, usage of GPU cores"),
+      ("RAM / SWAP", "used / total GB; app, wired, compressed, cached parts"),
+      ("CPU / GPU / ANE", "current power and temperature, DRAM / SRAM power"),
+      ("%/W", "rough efficiency: usage % (E + P clusters for CPU) per Watt"),
+      ("Power", "CPU + GPU + ANE power of SoC"),
+      ("Total", "whole system power, ~ if estimated without sensor"),
+      ("(x, y)", ""),
+      ("", ""),
+      ("Colors", ""),
+      ("border", "selected color, 'c' to change"),
+      ("yellow", "CPU throttled or memory pressure warning"),
+      ("red", "critical memory pressure or power over budget"),
+      ("", ""),
+      ("Keys", ""),
+      ("q", "quit"),
+      ("c / v / p", "change color / view / power stats"),
+      ("P", "apply next profile from config"),
+      ("i / d", "toggle instant power / deltas (▲ / ▼ vs previous sample)"),
+      ("s", "save snapshot to home directory"),
+      ("r", "reset charts and stats, start new measurement window"),
+      ("← / →", "scroll charts back / forward in history, Home / End – oldest / live"),
+      ("- / +", "decrease / increase update interval"),
+      ("?", "show / hide this help"),
+    ];
+
+    let stats = format!("power {} and {} over last {} samples", a.0, b.0, MAX_SPARKLINE);
+    let lines = items
+      .iter()
+      .map(|(k, v)| match (*k, *v) {
+        ("(x, y)", _) => format!("{:>16}  {}", format!("({}, {})", a.0, b.0), stats),
+        (k, "") => k.to_string(),
+        (k, v) => format!("{:>16}  {}", k, v),
+      })
+      .map(|x| Line::from(self.text(&x)))
+      .collect::<Vec<_>>();
+
+    let area = f.area();
+    let w = (lines.iter().map(|x| x.width()).max().unwrap_or(0) as u16 + 4).min(area.width);
+    let h = (lines.len() as u16 + 2).min(area.height);
+    let popup = Rect::new((area.width - w) / 2, (area.height - h) / 2, w, h);
+
+    let block = self.title_block("Help", "'?' to close");
+    let block = block.padding(Padding::horizontal(1));
+    f.render_widget(Clear, popup);
+    f.render_widget(Paragraph::new(lines).block(block), popup);
+  }
+
+  fn render_main(&mut self, f: &mut Frame) {
+    let area = f.area();
+    if self.cfg.compact
+      || self.force_compact
+      || area.width < COMPACT_MIN_WIDTH
+      || area.height < COMPACT_MIN_HEIGHT
+    {
+      return self.render_compact(f, area);
+    }
+
+    let mut label_l = format!(
       "{} ({}E+{}P+{}GPU {}GB)",
       self.soc.chip_name,
       self.soc.ecpu_cores,
@@ -335,6 +1161,19 @@ impl App {
       self.soc.memory_gb,
     );
 
+    // normal mode is not shown, only the one that changes power limits
+    if let Some(mode) = self.last.as_ref().map(|x| x.power_mode.as_str()) {
+      match mode {
+        "low" => label_l.push_str(" Low Power"),
+        "high" => label_l.push_str(" High Power"),
+        _ => {}
+      }
+    }
+
+    if self.temp.pkg_temp > 0.0 {
+      label_l.push_str(&format!(" SoC {}", self.temp_label(self.temp.pkg_temp as f64)));
+    }
+
     let rows = Layout::default()
       .direction(Direction::Vertical)
       .constraints([Constraint::Fill(2), Constraint::Fill(1)].as_ref())
@@ -345,72 +1184,359 @@ impl App {
     let iarea = block.inner(rows[0]);
     f.render_widget(block, rows[0]);
 
-    let iarea = Layout::default()
-      .direction(Direction::Vertical)
-      .constraints([Constraint::Fill(1), Constraint::Fill(1)].as_ref())
-      .split(iarea);
-
-    // 1st row
-    let (c1, c2) = h_stack(iarea[0]);
-    self.render_freq_block(f, c1, "E-CPU", &self.ecpu_freq);
-    self.render_freq_block(f, c2, "P-CPU", &self.pcpu_freq);
-
-    // 2nd row
-    let (c1, c2) = h_stack(iarea[1]);
-    self.render_mem_block(f, c1, &self.mem);
-    self.render_freq_block(f, c2, "GPU", &self.igpu_freq);
+    if self.cfg.view_type == ViewType::Full {
+      self.render_full_usage(f, iarea);
+    } else {
+      // temperature row only if enabled and sensors available
+      let temps = self.cfg.temp_charts && self.temp.cpu_temp_avg > 0.0;
+      let iarea = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Fill(1); if temps { 3 } else { 2 }])
+        .split(iarea);
+
+      // 1st row
+      let (c1, c2) = h_stack(iarea[0]);
+      let (ecpu_temp, pcpu_temp) = (self.temp.ecpu_temp_avg, self.temp.pcpu_temp_avg);
+      self.render_freq_block(f, c1, "E-CPU", &self.ecpu_freq, ecpu_temp);
+      self.render_freq_block(f, c2, "P-CPU", &self.pcpu_freq, pcpu_temp);
+
+      // 2nd row
+      let (c1, c2) = h_stack(iarea[1]);
+      self.render_mem_block(f, c1, &self.mem);
+      self.render_freq_block(f, c2, "GPU", &self.igpu_freq, 0.0);
+
+      if temps {
+        let (c1, c2) = h_stack(iarea[2]);
+        self.render_temp_block(f, c1, "CPU", &self.temps.cpu_items);
+        self.render_temp_block(f, c2, "GPU", &self.temps.gpu_items);
+      }
+    }
 
     // 3rd row
-    let label_l = format!(
-      "Power: {:.2}W (avg {:.2}W, max {:.2}W)",
-      self.all_power.top_value, self.all_power.avg_value, self.all_power.max_value,
+    let [a, b] = self.power_stats(&self.all_power);
+    let mut label_l = format!(
+      "Power: {}W{}{} ({} {}W, {} {}W)",
+      self.num(self.all_power.top_value),
+      self.delta_label(&self.all_power),
+      self.budget_label(self.all_power.top_value),
+      a.0,
+      self.num(a.1 as f64),
+      b.0,
+      self.num(b.1 as f64),
     );
 
+    if self.is_over_budget() {
+      label_l = format!("{} – OVER {:.0}W BUDGET", label_l, self.cfg.power_budget);
+    }
+
     // Show label only if sensor is available
     let label_r = if self.sys_power.top_value > 0.0 {
-      format!(
-        "Total {:.2}W ({:.2}, {:.2})",
-        self.sys_power.top_value, self.sys_power.avg_value, self.sys_power.max_value
-      )
+      let [a, b] = self.power_stats(&self.sys_power);
+      let mark = self.sys_power_mark();
+      let (val, a, b) = (self.sys_power.top_value, a.1 as f64, b.1 as f64);
+      format!("Total {}{}W ({}, {})", mark, self.num(val), self.num(a), self.num(b))
     } else {
       "".to_string()
     };
 
-    let block = self.title_block(&label_l, &label_r);
-    let usage = format!(" 'q' – quit, 'c' – color, 'v' – view | -/+ {}ms ", self.cfg.interval);
-    let block = block.title_bottom(Line::from(usage).right_aligned());
+    let mut block = self.title_block(&label_l, &label_r);
+    if self.is_over_budget() {
+      block = block.border_style(self.accent(Color::Red));
+    }
+
+    // other keys are listed in '?' popup, footer is shared with uptime, notice and error
+    let usage = format!(" 'q' – quit, '?' – help | -/+ {}ms ", self.cfg.interval);
+    let block = block.title_bottom(Line::from(self.text(&usage)).right_aligned());
+    let block = block.title_bottom(Line::from(self.text(&self.uptime_label())));
+    let block = match self.scroll > 0 {
+      true => block
+        .title_bottom(Line::from(self.text(&self.scroll_label())).fg(self.accent(Color::Yellow))),
+      false => block,
+    };
+    let block = match &self.notice {
+      Some((msg, at)) if at.elapsed().as_millis() < NOTICE_MSEC => {
+        block.title_bottom(Line::from(format!(" {} ", self.text(msg))))
+      }
+      _ => block,
+    };
+    let block = match &self.error {
+      Some(err) => {
+        block.title_bottom(Line::from(format!(" Error: {} ", err)).fg(self.accent(Color::Red)))
+      }
+      None => block,
+    };
     let iarea = block.inner(rows[1]);
     f.render_widget(block, rows[1]);
 
+    match self.cfg.view_type {
+      ViewType::Stacked => return self.render_stacked_power(f, iarea),
+      ViewType::Full => return self.render_full_power(f, iarea),
+      _ => {}
+    }
+
     let ha = Layout::default()
       .direction(Direction::Horizontal)
       .constraints([Constraint::Fill(1), Constraint::Fill(1), Constraint::Fill(1)].as_ref())
       .split(iarea);
 
-    f.render_widget(self.get_power_block("CPU", &self.cpu_power, self.temp.cpu_temp_avg), ha[0]);
-    f.render_widget(self.get_power_block("GPU", &self.gpu_power, self.temp.gpu_temp_avg), ha[1]);
-    f.render_widget(self.get_power_block("ANE", &self.ane_power, 0.0), ha[2]);
+    let (cpu_temp, gpu_temp) = (self.temp.cpu_temp_avg, self.temp.gpu_temp_avg);
+    let cpu_info = self.power_info(None, self.cpu_efficiency(), cpu_temp);
+    let gpu_info =
+      self.power_info(Some(("SRAM", &self.gpu_ram_power)), self.gpu_efficiency(), gpu_temp);
+    let ane_info = self.power_info(Some(("DRAM", &self.ram_power)), 0.0, 0.0);
+    self.render_power_block(f, ha[0], "CPU", &self.cpu_power, &cpu_info);
+    self.render_power_block(f, ha[1], "GPU", &self.gpu_power, &gpu_info);
+    self.render_power_block(f, ha[2], "ANE", &self.ane_power, &ane_info);
+  }
+
+  // small block with current value on first line and chart below, used by full view
+  fn render_tile(&self, f: &mut Frame, r: Rect, label: &str, value: &str, data: &[u64], max: u64) {
+    let block = self.title_block(label, "");
+    let area = block.inner(r);
+    f.render_widget(block, r);
+
+    let rows = Layout::default()
+      .direction(Direction::Vertical)
+      .constraints([Constraint::Length(1), Constraint::Fill(1)].as_ref())
+      .split(area);
+
+    f.render_widget(Paragraph::new(self.text(value)).style(self.color()).bold(), rows[0]);
+
+    let w = Sparkline::default().direction(RenderDirection::RightToLeft).data(self.view(data));
+    let w = w.bar_set(self.bar_set());
+    let w = if max > 0 { w.max(max) } else { w };
+    f.render_widget(w.style(self.color()), rows[1]);
+  }
+
+  // E-CPU / P-CPU / GPU / RAM in one row
+  fn render_full_usage(&self, f: &mut Frame, r: Rect) {
+    let cs = h_tiles(r, 4);
+    let freq = |val: &FreqStore, temp: f32| {
+      let freq = format_freq(val.top_value, self.cfg.precision);
+      match temp > 0.0 {
+        true => format!("{:3.0}% @ {} {}", val.usage * 100.0, freq, self.temp_label(temp as f64)),
+        false => format!("{:3.0}% @ {}", val.usage * 100.0, freq),
+      }
+    };
+    let (ecpu, pcpu) = (&self.ecpu_freq, &self.pcpu_freq);
+    self.render_tile(f, cs[0], "E-CPU", &freq(ecpu, self.temp.ecpu_temp_avg), &ecpu.items, 100);
+    self.render_tile(f, cs[1], "P-CPU", &freq(pcpu, self.temp.pcpu_temp_avg), &pcpu.items, 100);
+    self.render_tile(f, cs[2], "GPU", &freq(&self.igpu_freq, 0.0), &self.igpu_freq.items, 100);
+
+    let mem = &self.mem;
+    let ram = format!(
+      "{} / {} GB, SWAP {} GB",
+      self.num(mem.ram_usage as f64 / GB as f64),
+      self.num(mem.ram_total as f64 / GB as f64),
+      self.num(mem.swap_usage as f64 / GB as f64),
+    );
+    self.render_tile(f, cs[3], "RAM", &ram, &mem.items, mem.ram_total);
   }
 
-  pub fn run_loop(&mut self, interval: Option<u32>) -> WithError<()> {
+  // CPU / GPU / ANE / DRAM power in one row
+  fn render_full_power(&self, f: &mut Frame, r: Rect) {
+    let cs = h_tiles(r, 4);
+    let power = |val: &PowerStore, temp: f32| match temp > 0.0 {
+      true => format!("{}W {}", self.num(val.top_value), self.temp_label(temp as f64)),
+      false => format!("{}W", self.num(val.top_value)),
+    };
+
+    let (cpu_temp, gpu_temp) = (self.temp.cpu_temp_avg, self.temp.gpu_temp_avg);
+    let parts = [
+      ("CPU", &self.cpu_power, cpu_temp),
+      ("GPU", &self.gpu_power, gpu_temp),
+      ("ANE", &self.ane_power, 0.0),
+      ("DRAM", &self.ram_power, 0.0),
+    ];
+
+    for (i, (label, val, temp)) in parts.iter().enumerate() {
+      let (items, max) = self.power_chart(&val.items);
+      self.render_tile(f, cs[i], label, &power(val, *temp), &items, max.unwrap_or(0));
+    }
+  }
+
+  // CPU / GPU / ANE power history as one chart with common max, each part filled with own char
+  fn render_stacked_power(&self, f: &mut Frame, r: Rect) {
+    let parts =
+      [('█', "CPU", &self.cpu_power), ('▓', "GPU", &self.gpu_power), ('▒', "ANE", &self.ane_power)]
+        .map(|(c, name, val)| (self.glyph(c), name, val));
+
+    let label_l = parts
+      .iter()
+      .map(|(c, name, val)| format!("{} {} {}W", c, name, self.num(val.top_value)))
+      .collect::<Vec<_>>()
+      .join("  ");
+
+    let temps = [("CPU", self.temp.cpu_temp_avg), ("GPU", self.temp.gpu_temp_avg)];
+    let label_r = temps
+      .iter()
+      .filter(|(_, t)| *t > 0.0)
+      .map(|(name, t)| format!("{} {}", name, self.temp_label(*t as f64)))
+      .collect::<Vec<_>>()
+      .join(" ");
+
+    let block = self.title_block(&label_l, &label_r);
+    let area = block.inner(r);
+    f.render_widget(block, r);
+
+    let at = |val: &PowerStore, i: usize| self.view(&val.items).get(i).copied().unwrap_or(0);
+    let cols = (area.width as usize).min(self.view(&self.cpu_power.items).len());
+    let total = |i| parts.iter().map(|(_, _, val)| at(val, i)).sum::<u64>();
+    let max = self.power_max().unwrap_or_else(|| (0..cols).map(total).max().unwrap_or(0));
+    let max = max.max(1);
+
+    let buf = f.buffer_mut();
+    for i in 0..cols {
+      // newest value on the right side, as in other sparklines
+      let x = area.right() - i as u16 - 1;
+      let mut y = area.bottom();
+      let mut acc = 0;
+
+      for (c, _, val) in &parts {
+        acc += at(val, i);
+        let top = area.bottom() - (acc * area.height as u64 / max).min(area.height as u64) as u16;
+        while y > top {
+          y -= 1;
+          buf[(x, y)].set_char(*c).set_style(self.color());
+        }
+      }
+    }
+  }
+
+  pub fn run_loop(
+    &mut self,
+    interval: Option<u32>,
+    compact: bool,
+    no_color: bool,
+    ascii: bool,
+    power_max: Option<f32>,
+    carbon_intensity: Option<f32>,
+  ) -> WithError<()> {
+    self.force_compact = compact;
+    self.ascii = ascii;
+    if let Some(power_max) = power_max {
+      self.cfg.power_max = power_max;
+    }
+    if let Some(carbon_intensity) = carbon_intensity {
+      self.cfg.carbon_intensity = carbon_intensity;
+    }
+    self.no_color = no_color;
+    self.started_at = Some(Instant::now());
+    HISTORY_SIZE.store(self.cfg.history_size.max(MAX_SPARKLINE), Ordering::Relaxed);
+    self.boot_power = get_boot_power(&self.cfg.all_power);
+
+    let alerts =
+      self.cfg.notify.iter().map(|x| x.parse::<Alert>()).collect::<Result<Vec<_>, _>>()?;
+    self.add_notify(&alerts);
+
     // use from arg if provided, otherwise use config restored value
-    self.cfg.interval = interval.unwrap_or(self.cfg.interval).max(100).min(10_000);
+    self.cfg.interval = clamp_interval(interval.unwrap_or(self.cfg.interval));
     let msec = Arc::new(RwLock::new(self.cfg.interval));
+    let instant = Arc::new(RwLock::new(self.cfg.instant_power));
+    let filter = Arc::new(RwLock::new(MetricsFilter::new(&self.cfg.metrics)?));
 
     let (tx, rx) = mpsc::channel::<Event>();
-    run_inputs_thread(tx.clone(), 250);
-    run_sampler_thread(tx.clone(), msec.clone());
+    run_inputs_thread(tx.clone(), self.cfg.refresh.clamp(MIN_REFRESH, MAX_REFRESH));
+    run_sampler_thread(tx.clone(), msec.clone(), filter.clone(), &self.cfg);
+    run_instant_power_thread(tx.clone(), instant.clone(), &self.cfg);
+    run_display_thread(tx.clone());
+    // keep only thread senders, so recv fails when all of them died
+    drop(tx);
 
     let mut term = enter_term();
+    self.graphics = graphics::detect(self.cfg.graphics);
+    let mut tick = false;
+    let mut layout = (Vec::new(), false); // plot areas and help popup of last drawn images
 
     loop {
+      // only changed cells are written by ratatui, so tick redraw of same data does not flicker
       term.draw(|f| self.render(f)).unwrap();
+      let plots = self.plots.take();
+      if let Some(protocol) = self.graphics {
+        // on tick data is same, so images are kept on screen unless layout or help popup changed
+        let next = (plots.iter().map(|x| x.area).collect::<Vec<_>>(), self.show_help);
+        if !tick || next != layout {
+          // images would cover help popup
+          let plots = if self.show_help { vec![] } else { plots };
+          let _ = graphics::draw(protocol, &plots);
+          layout = next;
+        }
+      }
+
+      // all senders gone is possible only if threads died, nothing to wait for then
+      let event = match rx.recv() {
+        Ok(event) => event,
+        Err(_) => Event::Panic,
+      };
+      tick = matches!(event, Event::Tick);
 
-      match rx.recv()? {
+      match event {
         Event::Quit => break,
+        Event::Panic => {
+          if let Some(protocol) = self.graphics {
+            graphics::clear(protocol);
+          }
+          leave_term();
+          return Err("Background thread failed".into());
+        }
         Event::Update(data) => self.update_metrics(data),
-        Event::ChangeColor => self.cfg.next_color(),
-        Event::ChangeView => self.cfg.next_view_type(),
+        Event::InstantPower(data) => self.update_instant_power(data),
+        Event::Error(err) => self.error = Some(err),
+        Event::Redraw(w, h) => resize_term(&mut term, w, h),
+        Event::DisplaySleep(asleep) => {
+          *msec.write().unwrap() = if asleep { DISPLAY_SLEEP_MSEC } else { self.cfg.interval };
+          *instant.write().unwrap() = !asleep && self.cfg.instant_power;
+        }
+        Event::ChangeColor if !self.no_color => self.cfg.next_color(),
+        Event::ChangeView => {
+          self.cfg.next_view_type();
+          // sixel pixels stay on screen until cells under them are redrawn
+          if self.graphics.is_some() {
+            let _ = term.clear();
+          }
+        }
+        Event::ChangeStats => self.cfg.next_stats_type(),
+        Event::NextProfile => {
+          let msg = match self.cfg.next_profile() {
+            Some(name) => format!("Profile: {}", name),
+            None => "No profiles in config".to_string(),
+          };
+          self.notice = Some((msg, Instant::now()));
+
+          *msec.write().unwrap() = self.cfg.interval;
+          match MetricsFilter::new(&self.cfg.metrics) {
+            Ok(x) => *filter.write().unwrap() = x,
+            Err(e) => self.error = Some(e.to_string()),
+          }
+
+          if self.graphics.is_some() {
+            let _ = term.clear();
+          }
+        }
+        Event::ToggleInstant => {
+          self.cfg.toggle_instant_power();
+          *instant.write().unwrap() = self.cfg.instant_power;
+        }
+        Event::ToggleDeltas => self.cfg.toggle_deltas(),
+        Event::Scroll(delta) => self.scroll_by(delta),
+        Event::ScrollLive => self.scroll = 0,
+        Event::Reset => {
+          self.reset_stats();
+          self.notice = Some(("Stats reset".to_string(), Instant::now()));
+        }
+        Event::ToggleHelp => {
+          self.show_help = !self.show_help;
+          if self.graphics.is_some() {
+            let _ = term.clear();
+          }
+        }
+        Event::Snapshot => {
+          let msg = match self.save_snapshot() {
+            Ok(path) => format!("Saved to {}", path),
+            Err(e) => format!("Snapshot failed: {}", e),
+          };
+          self.notice = Some((msg, Instant::now()));
+        }
         Event::IncInterval => {
           self.cfg.inc_interval();
           *msec.write().unwrap() = self.cfg.interval;
@@ -423,7 +1549,120 @@ impl App {
       }
     }
 
+    if let Some(protocol) = self.graphics {
+      graphics::clear(protocol);
+    }
     leave_term();
+    let carbon = Some(self.cfg.carbon_intensity).filter(|x| *x > 0.0);
+    let mut summary = self.energy.summary(None, carbon);
+    summary.boot_avg_power = self.boot_power;
+    println!("{}", summary);
     Ok(())
   }
 }
+
+// MARK: Monitor
+
+// state of one remote host, metrics are kept in own App to reuse its rendering
+struct RemoteState {
+  view: App,
+}
+
+impl RemoteState {
+  fn new(host: &str, config: Option<&str>, no_color: bool, ascii: bool) -> Self {
+    let soc = SocInfo { chip_name: host.to_string(), ..Default::default() };
+    let error = Some("connecting...".to_string());
+    let cfg = Config::load(config);
+    let view = App { cfg, soc, no_color, ascii, error, ..Default::default() };
+    Self { view }
+  }
+}
+
+// split area to grid of nearly square cells
+fn grid(area: Rect, count: usize) -> Vec<Rect> {
+  let cols = (count as f64).sqrt().ceil().max(1.0) as usize;
+  let rows = count.div_ceil(cols);
+
+  let rows = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints(vec![Constraint::Fill(1); rows])
+    .split(area);
+
+  let mut cells = Vec::with_capacity(count);
+  for row in rows.iter() {
+    let cs = Layout::default()
+      .direction(Direction::Horizontal)
+      .constraints(vec![Constraint::Fill(1); cols])
+      .split(*row);
+    cells.extend(cs.iter().copied());
+  }
+
+  cells.truncate(count);
+  cells
+}
+
+pub fn run_monitor(
+  hosts: &[String],
+  interval: u32,
+  config: Option<&str>,
+  no_color: bool,
+  ascii: bool,
+) -> WithError<()> {
+  let states = hosts.iter().map(|x| RemoteState::new(x, config, no_color, ascii));
+  let mut states = states.collect::<Vec<_>>();
+
+  let (tx, rx) = mpsc::channel::<Event>();
+  run_inputs_thread(tx.clone(), 250);
+  for (idx, host) in hosts.iter().enumerate() {
+    run_remote_thread(tx.clone(), idx, host.clone(), interval);
+  }
+  drop(tx);
+
+  let mut term = enter_term();
+
+  loop {
+    term.draw(|f| {
+      for (state, cell) in states.iter().zip(grid(f.area(), hosts.len())) {
+        state.view.render_compact(f, cell);
+      }
+    })?;
+
+    match rx.recv() {
+      Ok(Event::Quit) => break,
+      Ok(Event::Panic) | Err(_) => {
+        leave_term();
+        return Err("Background thread failed".into());
+      }
+      Ok(Event::RemoteUpdate(idx, data)) => states[idx].view.update_metrics(data),
+      Ok(Event::RemoteError(idx, err)) => states[idx].view.error = Some(err),
+      Ok(Event::Redraw(w, h)) => resize_term(&mut term, w, h),
+      _ => {}
+    }
+  }
+
+  leave_term();
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn thread_panic_is_reported() {
+    let (tx, rx) = mpsc::channel::<Event>();
+    spawn_thread(tx, || panic!("sampler failed"));
+    let event = rx.recv_timeout(Duration::from_secs(5));
+    assert!(matches!(event, Ok(Event::Panic)));
+  }
+
+  #[test]
+  fn all_threads_gone_disconnects() {
+    let (tx, rx) = mpsc::channel::<Event>();
+    spawn_thread(tx.clone(), || {});
+    drop(tx);
+    // thread exits without event, channel closes instead of blocking forever
+    let event = rx.recv_timeout(Duration::from_secs(5));
+    assert!(matches!(event, Err(mpsc::RecvTimeoutError::Disconnected)));
+  }
+}
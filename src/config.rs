@@ -1,14 +1,92 @@
+use std::collections::BTreeMap;
+
 use ratatui::style::Color;
 use serde::{Deserialize, Serialize};
 use serde_inline_default::serde_inline_default;
 
+use crate::metrics::{PowerPart, TempSource, DEFAULT_POWER_PARTS};
+use crate::sources::SensorPrefixes;
+
+pub const MIN_INTERVAL: u32 = 100;
+pub const MAX_INTERVAL: u32 = 10_000;
+
+// sampling interval in ms, same bounds for all modes
+pub fn clamp_interval(msec: u32) -> u32 {
+  msec.clamp(MIN_INTERVAL, MAX_INTERVAL)
+}
+
+// TUI '-' / '+' keys move interval by step, keeping it in same bounds as clamp_interval
+const INTERVAL_STEP: u32 = 250;
+
+fn prev_interval(msec: u32) -> u32 {
+  let step = INTERVAL_STEP;
+  clamp_interval(msec.saturating_sub(step).div_ceil(step) * step)
+}
+
+fn next_interval(msec: u32) -> u32 {
+  let step = INTERVAL_STEP;
+  clamp_interval(msec.saturating_add(step) / step * step)
+}
+
 const COLORS_OPTIONS: [Color; 7] =
   [Color::Green, Color::Yellow, Color::Red, Color::Blue, Color::Magenta, Color::Cyan, Color::Reset];
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum ViewType {
   Sparkline,
   Gauge,
+  Stacked,
+  Full,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub enum StatsType {
+  AvgMax,
+  Percentiles,
+  MinMax,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum TempUnit {
+  Celsius,
+  Fahrenheit,
+}
+
+impl TempUnit {
+  // values are read in Celsius
+  pub fn convert(&self, celsius: f64) -> f64 {
+    match self {
+      TempUnit::Celsius => celsius,
+      TempUnit::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+    }
+  }
+
+  pub fn symbol(&self) -> &'static str {
+    match self {
+      TempUnit::Celsius => "°C",
+      TempUnit::Fahrenheit => "°F",
+    }
+  }
+}
+
+// named set of settings applied over config with --profile or 'P' key, missing fields are kept
+#[serde_inline_default]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+  #[serde_inline_default(None)]
+  pub interval: Option<u32>,
+
+  #[serde_inline_default(None)]
+  pub view_type: Option<ViewType>,
+
+  #[serde_inline_default(None)]
+  pub color: Option<Color>,
+
+  #[serde_inline_default(None)]
+  pub temp_unit: Option<TempUnit>,
+
+  #[serde_inline_default(None)]
+  pub metrics: Option<Vec<String>>,
 }
 
 #[serde_inline_default]
@@ -17,11 +95,132 @@ pub struct Config {
   #[serde_inline_default(ViewType::Sparkline)]
   pub view_type: ViewType,
 
+  #[serde_inline_default(StatsType::AvgMax)]
+  pub stats_type: StatsType,
+
   #[serde_inline_default(COLORS_OPTIONS[0])]
   pub color: Color,
 
   #[serde_inline_default(1000)]
   pub interval: u32,
+
+  #[serde_inline_default(false)]
+  pub instant_power: bool,
+
+  #[serde_inline_default(TempUnit::Celsius)]
+  pub temp_unit: TempUnit,
+
+  // metrics collected in TUI (same names as pipe --metrics), all when empty
+  #[serde_inline_default(Vec::new())]
+  pub metrics: Vec<String>,
+
+  // show change of power values vs previous sample
+  #[serde_inline_default(false)]
+  pub show_deltas: bool,
+
+  // always use compact layout, otherwise enabled only for small terminals
+  #[serde_inline_default(false)]
+  pub compact: bool,
+
+  // read temperature sensors only every N samples
+  #[serde_inline_default(1)]
+  pub temp_every: u32,
+
+  #[serde_inline_default(TempSource::Auto)]
+  pub temp_source: TempSource,
+
+  // SMC key of package temperature in header (e.g. "Tp0C"), max of CPU / GPU die sensors when empty
+  #[serde_inline_default(String::new())]
+  pub pkg_temp_key: String,
+
+  // name prefixes of SMC keys / HID sensors for E-CPU, P-CPU and GPU temperature, built-in if empty
+  #[serde_inline_default(Vec::new())]
+  pub ecpu_sensors: Vec<String>,
+
+  #[serde_inline_default(Vec::new())]
+  pub pcpu_sensors: Vec<String>,
+
+  #[serde_inline_default(Vec::new())]
+  pub gpu_sensors: Vec<String>,
+
+  // CPU / GPU temperature history as own row of charts
+  #[serde_inline_default(false)]
+  pub temp_charts: bool,
+
+  // number of samples kept for charts, older ones can be viewed by scrolling with arrow keys
+  #[serde_inline_default(1024)]
+  pub history_size: usize,
+
+  // redraw period in ms between samples (clock, notices, etc.), samples are drawn when ready
+  #[serde_inline_default(250)]
+  pub refresh: u64,
+
+  // residency state names counted as idle for CPU / GPU usage, built-in list when empty
+  #[serde_inline_default(Vec::new())]
+  pub idle_states: Vec<String>,
+
+  // number of sub-samples averaged per interval
+  #[serde_inline_default(4)]
+  pub measures: u32,
+
+  // smooth displayed values with previous ones
+  #[serde_inline_default(true)]
+  pub smoothing: bool,
+
+  // number of last samples averaged for displayed values (charts show raw values)
+  #[serde_inline_default(2)]
+  pub smoothing_window: usize,
+
+  // Watts, fixed max of power sparklines (e.g. chip TDP), auto-scale when 0
+  #[serde_inline_default(0.0)]
+  pub power_max: f32,
+
+  // power values summed to headline all_power
+  #[serde_inline_default(DEFAULT_POWER_PARTS.to_vec())]
+  pub all_power: Vec<PowerPart>,
+
+  // log scale of power charts, so low power changes are visible next to spikes
+  #[serde_inline_default(false)]
+  pub power_log_scale: bool,
+
+  // Watts, shows power as percent of budget when > 0
+  #[serde_inline_default(0.0)]
+  pub power_budget: f32,
+
+  // high-res charts with kitty graphics / sixel if terminal supports it, needs `graphics` feature
+  #[serde_inline_default(false)]
+  pub graphics: bool,
+
+  // decimals of displayed values, auto by magnitude when not set (1.23W, 12.3W, 123W)
+  #[serde_inline_default(None)]
+  pub precision: Option<usize>,
+
+  // gCO2/kWh of local electricity to estimate emissions in session summary, disabled when 0
+  #[serde_inline_default(0.0)]
+  pub carbon_intensity: f32,
+
+  // plain ASCII borders, charts and labels for terminals without UTF-8
+  #[serde_inline_default(false)]
+  pub ascii: bool,
+
+  // conditions like "cpu_temp>90" for desktop notifications from TUI, same format as watch --alert
+  #[serde_inline_default(Vec::new())]
+  pub notify: Vec<String>,
+
+  // seconds, min time between notifications of the same condition
+  #[serde_inline_default(300)]
+  pub notify_cooldown: u64,
+
+  #[serde_inline_default(BTreeMap::new())]
+  pub profiles: BTreeMap<String, Profile>,
+
+  // name of last applied profile
+  #[serde_inline_default(None)]
+  pub profile: Option<String>,
+
+  // custom file from --config, used for both load and save
+  #[serde(skip)]
+  path: Option<String>,
 }
 
 impl Default for Config {
@@ -31,7 +230,11 @@ impl Default for Config {
 }
 
 impl Config {
-  fn get_config_path() -> Option<String> {
+  fn get_config_path(path: Option<&str>) -> Option<String> {
+    if let Some(path) = path {
+      return Some(path.to_string());
+    }
+
     let home = match std::env::var("HOME") {
       Ok(home) => home,
       Err(_) => return None,
@@ -42,8 +245,19 @@ impl Config {
     Some(filepath)
   }
 
-  pub fn load() -> Self {
-    if let Some(path) = Self::get_config_path() {
+  // uses ~/.config/macmon.json if path not provided
+  pub fn load(path: Option<&str>) -> Self {
+    let mut config = Self::read(path);
+    config.path = path.map(|x| x.to_string());
+    config
+  }
+
+  pub fn sensor_prefixes(&self) -> SensorPrefixes {
+    SensorPrefixes::new(&self.ecpu_sensors, &self.pcpu_sensors, &self.gpu_sensors)
+  }
+
+  fn read(path: Option<&str>) -> Self {
+    if let Some(path) = Self::get_config_path(path) {
       let file = match std::fs::File::open(path) {
         Ok(file) => file,
         Err(_) => return Self::default(),
@@ -60,7 +274,7 @@ impl Config {
   }
 
   pub fn save(&self) {
-    if let Some(path) = Self::get_config_path() {
+    if let Some(path) = Self::get_config_path(self.path.as_deref()) {
       let file = match std::fs::File::create(path) {
         Ok(file) => file,
         Err(_) => return,
@@ -82,20 +296,119 @@ impl Config {
   pub fn next_view_type(&mut self) {
     self.view_type = match self.view_type {
       ViewType::Sparkline => ViewType::Gauge,
-      ViewType::Gauge => ViewType::Sparkline,
+      ViewType::Gauge => ViewType::Stacked,
+      ViewType::Stacked => ViewType::Full,
+      ViewType::Full => ViewType::Sparkline,
+    };
+    self.save();
+  }
+
+  pub fn next_stats_type(&mut self) {
+    self.stats_type = match self.stats_type {
+      StatsType::AvgMax => StatsType::Percentiles,
+      StatsType::Percentiles => StatsType::MinMax,
+      StatsType::MinMax => StatsType::AvgMax,
+    };
+    self.save();
+  }
+
+  pub fn toggle_instant_power(&mut self) {
+    self.instant_power = !self.instant_power;
+    self.save();
+  }
+
+  pub fn toggle_deltas(&mut self) {
+    self.show_deltas = !self.show_deltas;
+    self.save();
+  }
+
+  // copies profile values over current ones, so changes after it are kept as usual
+  pub fn apply_profile(&mut self, name: &str) -> Result<(), String> {
+    let profile = match self.profiles.get(name) {
+      Some(x) => x.clone(),
+      None => {
+        let names = self.profiles.keys().cloned().collect::<Vec<_>>();
+        let names = if names.is_empty() { "none".to_string() } else { names.join(", ") };
+        return Err(format!("Unknown profile: {} (available: {})", name, names));
+      }
     };
+
+    if let Some(interval) = profile.interval {
+      self.interval = clamp_interval(interval);
+    }
+    if let Some(view_type) = profile.view_type {
+      self.view_type = view_type;
+    }
+    if let Some(color) = profile.color {
+      self.color = color;
+    }
+    if let Some(temp_unit) = profile.temp_unit {
+      self.temp_unit = temp_unit;
+    }
+    if let Some(metrics) = profile.metrics {
+      self.metrics = metrics;
+    }
+    self.profile = Some(name.to_string());
     self.save();
+    Ok(())
+  }
+
+  // profiles in name order, returns applied one or None if config has no profiles
+  pub fn next_profile(&mut self) -> Option<String> {
+    let names = self.profiles.keys().cloned().collect::<Vec<_>>();
+    let idx = match names.iter().position(|x| Some(x) == self.profile.as_ref()) {
+      Some(idx) => (idx + 1) % names.len(),
+      None => 0,
+    };
+
+    let name = names.get(idx)?.clone();
+    self.apply_profile(&name).ok()?;
+    Some(name)
   }
 
   pub fn dec_interval(&mut self) {
-    let step = 250;
-    self.interval = ((self.interval.saturating_sub(step) + step - 1) / step * step).max(step);
+    self.interval = prev_interval(self.interval);
     self.save();
   }
 
   pub fn inc_interval(&mut self) {
-    let step = 250;
-    self.interval = (self.interval.saturating_add(step) / step * step).min(10_000);
+    self.interval = next_interval(self.interval);
     self.save();
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn clamp_interval_bounds() {
+    assert_eq!(clamp_interval(0), MIN_INTERVAL);
+    assert_eq!(clamp_interval(MIN_INTERVAL - 1), MIN_INTERVAL);
+    assert_eq!(clamp_interval(MIN_INTERVAL), MIN_INTERVAL);
+    assert_eq!(clamp_interval(1000), 1000);
+    assert_eq!(clamp_interval(MAX_INTERVAL), MAX_INTERVAL);
+    assert_eq!(clamp_interval(MAX_INTERVAL + 1), MAX_INTERVAL);
+    assert_eq!(clamp_interval(u32::MAX), MAX_INTERVAL);
+  }
+
+  #[test]
+  fn prev_interval_bounds() {
+    assert_eq!(prev_interval(1000), 750);
+    assert_eq!(prev_interval(900), 750);
+    assert_eq!(prev_interval(250), MIN_INTERVAL);
+    assert_eq!(prev_interval(MIN_INTERVAL), MIN_INTERVAL);
+    assert_eq!(prev_interval(0), MIN_INTERVAL);
+    assert_eq!(prev_interval(u32::MAX), MAX_INTERVAL);
+  }
+
+  #[test]
+  fn next_interval_bounds() {
+    assert_eq!(next_interval(MIN_INTERVAL), 250);
+    assert_eq!(next_interval(750), 1000);
+    assert_eq!(next_interval(900), 1000);
+    assert_eq!(next_interval(MAX_INTERVAL - 1), MAX_INTERVAL);
+    assert_eq!(next_interval(MAX_INTERVAL), MAX_INTERVAL);
+    assert_eq!(next_interval(u32::MAX), MAX_INTERVAL);
+  }
+}
@@ -1,8 +1,11 @@
 use core_foundation::base::CFRelease;
+use serde_json::json;
 
+use crate::metrics::{get_boot_power, smc_startup_timings, Sampler, DEFAULT_POWER_PARTS};
 use crate::sources::{
-  cfdict_keys, cfio_get_props, cfio_get_residencies, cfio_watts, get_dvfs_mhz, run_system_profiler,
-  IOHIDSensors, IOReport, IOServiceIterator, SMC,
+  cfdict_keys, cfio_energy, cfio_get_props, cfio_get_residencies, cfio_watts, get_dvfs_mhz,
+  run_system_profiler, sort_sensors, IOHIDSensors, IOReport, IOServiceIterator, PLimitData,
+  SensorGroup, SensorOrder, SensorPrefixes, SocInfo, SMC,
 };
 
 type WithError<T> = Result<T, Box<dyn std::error::Error>>;
@@ -17,7 +20,33 @@ fn print_divider(msg: &str) {
   println!("\n--- {} {}", msg, "-".repeat(len));
 }
 
-pub fn print_debug() -> WithError<()> {
+// keep sensors matched by pattern, and only N hottest if top_n set
+fn filter_sensors(
+  mut items: Vec<(String, f32)>,
+  top_n: Option<usize>,
+  filter: Option<&str>,
+) -> Vec<(String, f32)> {
+  if let Some(filter) = filter {
+    items.retain(|(name, _)| name.contains(filter));
+  }
+
+  if let Some(top_n) = top_n {
+    items.sort_by(|a, b| b.1.total_cmp(&a.1));
+    items.truncate(top_n);
+  }
+
+  items
+}
+
+// order and prefix are applied to HID sensors only, SMC keys are not grouped by kind;
+// kind order uses same sensor prefixes as averages (from config)
+pub fn print_debug(
+  top_n: Option<usize>,
+  filter: Option<&str>,
+  order: SensorOrder,
+  prefix: Option<&str>,
+  prefixes: &SensorPrefixes,
+) -> WithError<()> {
   let out = run_system_profiler()?;
 
   let chip =
@@ -36,7 +65,7 @@ pub fn print_debug() -> WithError<()> {
   for (entry, name) in IOServiceIterator::new("AppleARMIODevice")? {
     if name == "pmgr" {
       let item = cfio_get_props(entry, name)?;
-      let mut keys = cfdict_keys(item);
+      let mut keys = unsafe { cfdict_keys(item) };
       keys.sort();
 
       for key in keys {
@@ -44,7 +73,7 @@ pub fn print_debug() -> WithError<()> {
           continue;
         }
 
-        let (volts, freqs) = get_dvfs_mhz(item, &key);
+        let (volts, freqs) = unsafe { get_dvfs_mhz(item, &key) };
         let volts = volts.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(" ");
         let freqs = freqs.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(" ");
         println!("{:>32}: (v) {}", key, volts);
@@ -65,11 +94,19 @@ pub fn print_debug() -> WithError<()> {
 
   let dur = 100;
   let ior = IOReport::new(channels)?;
+  if let Ok(mut soc) = SocInfo::new() {
+    soc.set_clusters(&ior);
+    println!("Clusters: {} E-CPU, {} P-CPU", soc.ecpu_clusters, soc.pcpu_clusters);
+  }
+
   for x in ior.get_sample(dur) {
     let msg = format!("{} :: {} :: {} ({}) =", x.group, x.subgroup, x.channel, x.unit);
     match x.unit.as_str() {
-      "24Mticks" => println!("{} {:?}", msg, cfio_get_residencies(x.item)),
-      _ => println!("{} {:.2}W", msg, cfio_watts(x.item, &x.unit, dur)?),
+      "24Mticks" => println!("{} {:?}", msg, unsafe { cfio_get_residencies(x.item) }),
+      _ => {
+        let (watts, raw) = unsafe { (cfio_watts(x.item, &x.unit, dur)?, cfio_energy(x.item)) };
+        println!("{} {:.2}W (raw: {}{})", msg, watts, raw, x.unit);
+      }
     }
   }
 
@@ -78,6 +115,8 @@ pub fn print_debug() -> WithError<()> {
 
   let mut smc = SMC::new()?;
   let keys = smc.read_all_keys().unwrap_or(vec![]);
+  let mut items = Vec::new();
+  let mut temp_keys = Vec::new();
   for key in &keys {
     if !key.starts_with("T") {
       continue;
@@ -87,28 +126,255 @@ pub fn print_debug() -> WithError<()> {
     if !(ki.data_type == FLOAT_TYPE && ki.data_size == 4) {
       continue;
     }
+    temp_keys.push(key.clone());
 
     let val = smc.read_val(&key);
     if val.is_err() {
       continue;
     }
 
-    let val = val.unwrap();
-    let val = f32::from_le_bytes(val.data.clone().try_into().unwrap());
+    let val = match val.unwrap().decode_f32() {
+      Ok(val) => val,
+      Err(_) => continue,
+    };
     if val < 20.0 || val > 99.0 {
       continue;
     }
 
+    items.push((key.clone(), val));
+  }
+
+  for (key, val) in filter_sensors(items, top_n, filter) {
     print!("{}={:.2}  ", key, val);
   }
 
   println!(""); // close previous line
 
+  // cost of one sample temperature read, sampler reads only classified part of these keys
+  let started = std::time::Instant::now();
+  let vals = smc.read_f32_each(&temp_keys);
+  let elapsed = started.elapsed().as_secs_f64() * 1000.0;
+  let failed = smc.failed_reads();
+  println!(
+    "Read {} of {} keys in {:.2}ms, failed: {}",
+    vals.len(),
+    temp_keys.len(),
+    elapsed,
+    failed
+  );
+
+  print_divider("SMC power limits");
+  match smc.read_power_limits() {
+    Ok(x) => println!("{}", fmt_power_limits(&x)),
+    Err(e) => println!("Not available: {}", e),
+  }
+
   print_divider("IOHID");
-  let hid = IOHIDSensors::new()?;
-  for (key, val) in hid.get_metrics() {
+  let items = IOHIDSensors::new()?.get_metrics_by(order, prefix, prefixes);
+  // order is applied again, top_n keeps hottest
+  let mut items = filter_sensors(items, top_n, filter);
+  sort_sensors(&mut items, order, prefixes);
+  for (key, val) in items {
     println!("{:>32}: {:6.2}", key, val);
   }
 
   Ok(())
 }
+
+// raw SMC values, 0 means platform does not limit this part now
+fn fmt_power_limits(x: &PLimitData) -> String {
+  let fmt = |x: u32| if x == 0 { "none".to_string() } else { x.to_string() };
+  let (cpu, gpu, mem) = (fmt(x.cpu_p_limit), fmt(x.gpu_p_limit), fmt(x.mem_p_limit));
+  format!("CPU {}, GPU {}, RAM {} (v{})", cpu, gpu, mem, x.version)
+}
+
+pub fn print_info(json: bool) -> WithError<()> {
+  let mut soc = SocInfo::new()?;
+  soc.set_clusters(&IOReport::new(vec![("CPU Stats", Some("CPU Complex Performance States"))])?);
+  if json {
+    println!("{}", serde_json::to_string_pretty(&soc)?);
+    return Ok(());
+  }
+
+  let freqs = |x: &[u32]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ");
+  println!("{:>12}: {}", "Chip", soc.chip_name);
+  println!("{:>12}: {}", "Model", soc.mac_model);
+  println!("{:>12}: {} GB", "Memory", soc.memory_gb);
+  println!("{:>12}: {}", "Dies", soc.die_count);
+  let (e, p) = ((soc.ecpu_cores, soc.ecpu_clusters), (soc.pcpu_cores, soc.pcpu_clusters));
+  println!("{:>12}: {} cores in {} clusters, {} MHz", "E-CPU", e.0, e.1, freqs(&soc.ecpu_freqs));
+  println!("{:>12}: {} cores in {} clusters, {} MHz", "P-CPU", p.0, p.1, freqs(&soc.pcpu_freqs));
+  println!("{:>12}: {} cores, {} MHz", "GPU", soc.gpu_cores, freqs(&soc.gpu_freqs));
+  println!("{:>12}: {}", "Rosetta", if soc.proc_translated { "yes" } else { "no" });
+  if let Some(x) = get_boot_power(DEFAULT_POWER_PARTS) {
+    println!("{:>12}: {:.2} W average SoC power", "Since boot", x);
+  }
+  if let Ok(x) = SMC::new().and_then(|mut smc| smc.read_power_limits()) {
+    println!("{:>12}: {}", "Power limit", fmt_power_limits(&x));
+  }
+  Ok(())
+}
+
+// cold / warm start cost of SMC key list, numbers for readme and cache related reports
+pub fn print_startup() -> WithError<()> {
+  let (cold, warm) = smc_startup_timings()?;
+  println!("{:>14}: {:.1} ms", "SMC keys scan", cold.as_secs_f64() * 1000.0);
+  println!("{:>14}: {:.1} ms", "SMC keys cache", warm.as_secs_f64() * 1000.0);
+
+  // whole sampler with all caches in place, as on usual start
+  let started = std::time::Instant::now();
+  drop(Sampler::new()?);
+  println!("{:>14}: {:.1} ms", "Sampler (warm)", started.elapsed().as_secs_f64() * 1000.0);
+  Ok(())
+}
+
+// shows how sensors are averaged, to help with own ecpu_sensors / pcpu_sensors / gpu_sensors
+pub fn print_sensor_groups(prefixes: &SensorPrefixes, filter: Option<&str>) -> WithError<()> {
+  const FLOAT_TYPE: u32 = 1718383648; // FourCC: "flt "
+
+  let mut items = Vec::new();
+  let mut smc = SMC::new()?;
+  for key in smc.read_all_keys().unwrap_or(vec![]) {
+    match smc.read_key_info(&key) {
+      Ok(ki) if key.starts_with('T') && ki.data_type == FLOAT_TYPE && ki.data_size == 4 => {}
+      _ => continue,
+    }
+
+    if let Ok(val) = smc.read_val(&key).and_then(|x| x.decode_f32()) {
+      items.push(("SMC", key, val));
+    }
+  }
+
+  for (name, val) in IOHIDSensors::new()?.get_metrics() {
+    items.push(("HID", name, val));
+  }
+
+  println!("{:>6} {:>32} {:>8}  Group", "Source", "Name", "Value");
+  for (source, name, val) in items {
+    if filter.is_some_and(|x| !name.contains(x)) {
+      continue;
+    }
+
+    let group = match prefixes.classify(&name) {
+      Some(SensorGroup::Ecpu) => "E-CPU",
+      Some(SensorGroup::Pcpu) => "P-CPU",
+      Some(SensorGroup::Gpu) => "GPU",
+      None => "-",
+    };
+
+    println!("{:>6} {:>32} {:>8.2}  {}", source, name, val, group);
+  }
+
+  Ok(())
+}
+
+// same data as print_debug, but as one JSON document to attach to bug reports
+pub fn print_debug_json() -> WithError<()> {
+  let out = run_system_profiler()?;
+  let hw = &out["SPHardwareDataType"][0];
+
+  let mut dvfs = serde_json::Map::new();
+  for (entry, name) in IOServiceIterator::new("AppleARMIODevice")? {
+    if name == "pmgr" {
+      let item = cfio_get_props(entry, name)?;
+      for key in unsafe { cfdict_keys(item) } {
+        if !key.contains("voltage-states") {
+          continue;
+        }
+
+        let (volts, freqs) = unsafe { get_dvfs_mhz(item, &key) };
+        dvfs.insert(key, json!({ "volts": volts, "freqs": freqs }));
+      }
+
+      unsafe { CFRelease(item as _) }
+    }
+  }
+
+  let channels = vec![
+    ("Energy Model", None),
+    ("CPU Stats", Some("CPU Complex Performance States")),
+    ("CPU Stats", Some("CPU Core Performance States")),
+    ("GPU Stats", Some("GPU Performance States")),
+  ];
+
+  let dur = 100;
+  let ior = IOReport::new(channels)?;
+  let mut ioreport = Vec::new();
+  for x in ior.get_sample(dur) {
+    let mut entry = json!({
+      "group": x.group,
+      "subgroup": x.subgroup,
+      "channel": x.channel,
+      "unit": x.unit,
+    });
+
+    match x.unit.as_str() {
+      "24Mticks" => {
+        let items = unsafe { cfio_get_residencies(x.item) };
+        let items: Vec<_> = items.iter().map(|(k, v)| json!({ "name": k, "value": v })).collect();
+        entry["residencies"] = json!(items);
+      }
+      _ => {
+        entry["value"] = json!(unsafe { cfio_energy(x.item) });
+        entry["watts"] = json!(unsafe { cfio_watts(x.item, &x.unit, dur) }.ok());
+      }
+    }
+
+    ioreport.push(entry);
+  }
+
+  const FLOAT_TYPE: u32 = 1718383648; // FourCC: "flt "
+
+  let mut smc = SMC::new()?;
+  let mut smc_keys = serde_json::Map::new();
+  for key in smc.read_all_keys().unwrap_or(vec![]) {
+    let ki = match smc.read_key_info(&key) {
+      Ok(ki) => ki,
+      Err(_) => continue,
+    };
+
+    if !(ki.data_type == FLOAT_TYPE && ki.data_size == 4) {
+      continue;
+    }
+
+    if let Ok(val) = smc.read_val(&key).and_then(|x| x.decode_f32()) {
+      smc_keys.insert(key, json!(val));
+    }
+  }
+
+  let hid = IOHIDSensors::new()?;
+  let hid: serde_json::Map<_, _> =
+    hid.get_metrics().into_iter().map(|(k, v)| (k, json!(v))).collect();
+
+  let doc = json!({
+    "chip": hw["chip_type"],
+    "model": hw["machine_model"],
+    "os": out["SPSoftwareDataType"][0]["os_version"],
+    "procs": hw["number_processors"],
+    "dvfs": dvfs,
+    "ioreport": ioreport,
+    "smc": smc_keys,
+    "power_limits": smc.read_power_limits().ok(),
+    "hid": hid,
+  });
+
+  println!("{}", serde_json::to_string_pretty(&doc)?);
+  Ok(())
+}
+
+pub fn print_smc_keys(keys: &[String], interval: u32) -> WithError<()> {
+  let mut smc = SMC::new()?;
+
+  loop {
+    let mut items = Vec::with_capacity(keys.len());
+    for key in keys {
+      match smc.read_val(key) {
+        Ok(val) => items.push(format!("{}={} ({})", key, val.decode(), val.unit.trim())),
+        Err(err) => items.push(format!("{}=({})", key, err)),
+      }
+    }
+
+    println!("{}", items.join("  "));
+    std::thread::sleep(std::time::Duration::from_millis(interval as u64));
+  }
+}
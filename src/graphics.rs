@@ -0,0 +1,244 @@
+// High-resolution charts for terminals with kitty graphics protocol or sixel support.
+// TUI leaves chart areas empty and images are drawn over them after each frame.
+
+use std::io::Write;
+
+use ratatui::{crossterm::terminal, layout::Rect, style::Color};
+
+type WithError<T> = Result<T, Box<dyn std::error::Error>>;
+
+const KITTY_CHUNK: usize = 4096;
+const LINE_PX: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+  Kitty,
+  Sixel,
+}
+
+// values are newest first (same as sparkline items), auto-scale when max is 0
+#[derive(Debug, Clone)]
+pub struct Plot {
+  pub area: Rect,
+  pub items: Vec<u64>,
+  pub max: u64,
+  pub color: Color,
+}
+
+// terminal capability by env variables, querying terminal would mix with input events
+pub fn detect(enabled: bool) -> Option<Protocol> {
+  if !cfg!(feature = "graphics") || !enabled {
+    return None;
+  }
+
+  let term = std::env::var("TERM").unwrap_or_default();
+  let program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+
+  if std::env::var("KITTY_WINDOW_ID").is_ok()
+    || term == "xterm-kitty"
+    || term == "xterm-ghostty"
+    || program == "WezTerm"
+    || program == "ghostty"
+  {
+    return Some(Protocol::Kitty);
+  }
+
+  if term.contains("sixel") || term.starts_with("foot") || term == "mlterm" {
+    return Some(Protocol::Sixel);
+  }
+
+  None
+}
+
+// pixel size of one cell, common default if terminal does not report it
+fn cell_size() -> (usize, usize) {
+  match terminal::window_size() {
+    Ok(x) if x.width > 0 && x.height > 0 && x.columns > 0 && x.rows > 0 => {
+      ((x.width / x.columns) as usize, (x.height / x.rows) as usize)
+    }
+    _ => (8, 16),
+  }
+}
+
+fn to_rgb(color: Color) -> (u8, u8, u8) {
+  match color {
+    Color::Rgb(r, g, b) => (r, g, b),
+    Color::Red => (205, 49, 49),
+    Color::Green => (13, 188, 121),
+    Color::Yellow => (229, 229, 16),
+    Color::Blue => (36, 114, 200),
+    Color::Magenta => (188, 63, 188),
+    Color::Cyan => (17, 168, 205),
+    _ => (204, 204, 204),
+  }
+}
+
+// line chart, one sample per cell column as in sparkline, linear interpolation between them
+fn rasterize(plot: &Plot, w: usize, h: usize, cell_w: usize) -> Vec<bool> {
+  let mut px = vec![false; w * h];
+  if plot.items.is_empty() || w == 0 || h == 0 {
+    return px;
+  }
+
+  let max = match plot.max {
+    0 => plot.items.iter().max().copied().unwrap_or(0),
+    x => x,
+  };
+
+  let max = max.max(1) as f64;
+  let last = plot.items.len() - 1;
+  let at = |i: usize| plot.items[i.min(last)] as f64;
+
+  let mut prev: Option<usize> = None;
+  for x in 0..w {
+    // newest value on the right side
+    let pos = (w - 1 - x) as f64 / cell_w as f64;
+    if pos > last as f64 {
+      continue;
+    }
+
+    let (i, t) = (pos.floor() as usize, pos.fract());
+    let val = at(i) * (1.0 - t) + at(i + 1) * t;
+    let y = h - 1 - ((val / max).min(1.0) * (h - 1) as f64).round() as usize;
+
+    // connect with previous column, so steep changes have no gaps
+    let (y1, y2) = match prev {
+      Some(p) => (y.min(p), y.max(p)),
+      None => (y, y),
+    };
+
+    for yy in y1..=(y2 + LINE_PX - 1).min(h - 1) {
+      px[yy * w + x] = true;
+    }
+
+    prev = Some(y);
+  }
+
+  px
+}
+
+fn base64(data: &[u8]) -> String {
+  const ABC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+  let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+  for chunk in data.chunks(3) {
+    let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+    let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+    for i in 0..4 {
+      match i <= chunk.len() {
+        true => out.push(ABC[(n >> (18 - i * 6) & 63) as usize] as char),
+        false => out.push('='),
+      }
+    }
+  }
+
+  out
+}
+
+// RGBA image with transparent background, cursor is not moved (C=1)
+fn encode_kitty(px: &[bool], w: usize, h: usize, color: Color) -> String {
+  let (r, g, b) = to_rgb(color);
+  let mut rgba = Vec::with_capacity(w * h * 4);
+  for &on in px {
+    rgba.extend_from_slice(&if on { [r, g, b, 255] } else { [0, 0, 0, 0] });
+  }
+
+  let data = base64(&rgba);
+  let chunks = data.as_bytes().chunks(KITTY_CHUNK).collect::<Vec<_>>();
+
+  let mut out = String::new();
+  for (i, chunk) in chunks.iter().enumerate() {
+    let more = if i + 1 < chunks.len() { 1 } else { 0 };
+    let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+    match i {
+      0 => out.push_str(&format!("\x1b_Ga=T,f=32,s={w},v={h},q=2,C=1,m={more};{chunk}\x1b\\")),
+      _ => out.push_str(&format!("\x1b_Gm={more};{chunk}\x1b\\")),
+    }
+  }
+
+  out
+}
+
+// two-color sixel: 0 is terminal background, 1 is chart color; runs are RLE compressed
+fn encode_sixel(px: &[bool], w: usize, h: usize, color: Color) -> String {
+  let (r, g, b) = to_rgb(color);
+  let pct = |x: u8| x as u32 * 100 / 255;
+  let mut out = format!("\x1bPq\"1;1;{w};{h}#1;2;{};{};{}", pct(r), pct(g), pct(b));
+
+  for band in 0..h.div_ceil(6) {
+    out.push_str("#1");
+
+    let mut run: Option<(char, usize)> = None;
+    for x in 0..w {
+      let mut bits = 0u8;
+      for k in 0..6 {
+        let y = band * 6 + k;
+        if y < h && px[y * w + x] {
+          bits |= 1 << k;
+        }
+      }
+
+      let c = (63 + bits) as char;
+      run = match run {
+        Some((rc, n)) if rc == c => Some((rc, n + 1)),
+        Some((rc, n)) => {
+          push_run(&mut out, rc, n);
+          Some((c, 1))
+        }
+        None => Some((c, 1)),
+      };
+    }
+
+    if let Some((rc, n)) = run {
+      push_run(&mut out, rc, n);
+    }
+
+    out.push('-');
+  }
+
+  out.push_str("\x1b\\");
+  out
+}
+
+fn push_run(out: &mut String, c: char, n: usize) {
+  match n {
+    1..=3 => out.push_str(&c.to_string().repeat(n)),
+    _ => out.push_str(&format!("!{}{}", n, c)),
+  }
+}
+
+// draws plots over current frame, previous kitty images are removed first
+pub fn draw(protocol: Protocol, plots: &[Plot]) -> WithError<()> {
+  let (cell_w, cell_h) = cell_size();
+  let mut out = String::new();
+
+  if protocol == Protocol::Kitty {
+    out.push_str("\x1b_Ga=d,d=A,q=2\x1b\\");
+  }
+
+  for plot in plots {
+    let (w, h) = (plot.area.width as usize * cell_w, plot.area.height as usize * cell_h);
+    if w == 0 || h == 0 {
+      continue;
+    }
+
+    let px = rasterize(plot, w, h, cell_w);
+    out.push_str(&format!("\x1b[{};{}H", plot.area.y + 1, plot.area.x + 1));
+    match protocol {
+      Protocol::Kitty => out.push_str(&encode_kitty(&px, w, h, plot.color)),
+      Protocol::Sixel => out.push_str(&encode_sixel(&px, w, h, plot.color)),
+    }
+  }
+
+  let mut stdout = std::io::stdout();
+  stdout.write_all(out.as_bytes())?;
+  stdout.flush()?;
+  Ok(())
+}
+
+// images are not part of TUI buffer, so they should be removed before leaving
+pub fn clear(protocol: Protocol) {
+  if protocol == Protocol::Kitty {
+    let _ = draw(protocol, &[]);
+  }
+}
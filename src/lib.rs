@@ -0,0 +1,7 @@
+// Sampling of Apple Silicon metrics without sudo, shared by macmon CLI and other tools
+pub mod metrics;
+pub mod sink;
+pub mod sources;
+
+#[cfg(feature = "async")]
+pub mod stream;
@@ -0,0 +1,97 @@
+// Output of `log` command: date-stamped files in directory, rotated by size.
+// Size is checked on flush, pipe loop flushes after each sample, so lines are never split.
+// With `--header` first line written is kept and repeated at top of each new file, so every
+// file is self-describing.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+type WithError<T> = Result<T, Box<dyn std::error::Error>>;
+
+pub struct RotatingFile {
+  dir: PathBuf,
+  ext: String,
+  max_bytes: u64,
+  gzip: bool,
+  path: PathBuf,
+  file: BufWriter<File>,
+  written: u64,
+  header: Option<Vec<u8>>,
+}
+
+impl RotatingFile {
+  pub fn new(dir: &Path, ext: &str, max_bytes: u64, gzip: bool) -> WithError<Self> {
+    std::fs::create_dir_all(dir)?;
+    let (path, file) = Self::create(dir, ext)?;
+    let (dir, ext) = (dir.to_path_buf(), ext.to_string());
+    Ok(Self { dir, ext, max_bytes, gzip, path, file, written: 0, header: None })
+  }
+
+  pub fn set_header(&mut self, enabled: bool) {
+    self.header = enabled.then(Vec::new);
+  }
+
+  // macmon-20250101-120000.jsonl, suffix added if file for this second already exists
+  fn create(dir: &Path, ext: &str) -> std::io::Result<(PathBuf, BufWriter<File>)> {
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let mut path = dir.join(format!("macmon-{}.{}", stamp, ext));
+    let mut idx = 1;
+    while path.exists() {
+      path = dir.join(format!("macmon-{}-{}.{}", stamp, idx, ext));
+      idx += 1;
+    }
+
+    let file = File::options().create_new(true).write(true).open(&path)?;
+    Ok((path, BufWriter::new(file)))
+  }
+
+  fn rotate(&mut self) -> std::io::Result<()> {
+    self.file.flush()?;
+    let (path, file) = Self::create(&self.dir, &self.ext)?;
+    let prev = std::mem::replace(&mut self.path, path);
+    self.file = file;
+    self.written = 0;
+
+    if let Some(header) = &self.header {
+      self.file.write_all(header)?;
+      self.written = header.len() as u64;
+    }
+
+    // system gzip in background, sampling should not wait for compression
+    if self.gzip {
+      std::thread::spawn(move || {
+        if let Err(e) = std::process::Command::new("gzip").arg(&prev).status() {
+          eprintln!("gzip {} failed: {}", prev.display(), e);
+        }
+      });
+    }
+
+    Ok(())
+  }
+}
+
+impl Write for RotatingFile {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    let n = self.file.write(buf)?;
+    self.written += n as u64;
+
+    // header line may come in several writes, collect until first newline
+    if let Some(header) = self.header.as_mut().filter(|x| !x.ends_with(b"\n")) {
+      let buf = &buf[..n];
+      let end = buf.iter().position(|x| *x == b'\n').map_or(n, |x| x + 1);
+      header.extend_from_slice(&buf[..end]);
+    }
+
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    self.file.flush()?;
+    if self.max_bytes > 0 && self.written >= self.max_bytes {
+      self.rotate()?;
+    }
+
+    Ok(())
+  }
+}
@@ -1,26 +1,269 @@
 mod app;
 mod config;
 mod debug;
-mod metrics;
-mod sources;
+mod graphics;
+mod logfile;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod remote;
+#[cfg(feature = "serve")]
+mod serve;
+mod top;
+mod watch;
+
+use macmon::{metrics, sources};
+use sources::{SensorOrder, SensorPrefixes};
 
 use app::App;
-use clap::{parser::ValueSource, CommandFactory, Parser, Subcommand};
-use metrics::Sampler;
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::{parser::ValueSource, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use metrics::{
+  flat_fields, get_boot_power, session_power, units_json, zero_div, EnergyCounter, Metrics,
+  MetricsFilter, PowerHistory, PowerPart, PowerStats, Sampler, TempSource,
+};
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::error::Error;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum TimestampFormat {
+  #[value(name = "rfc3339")]
+  Rfc3339,
+  #[value(name = "epoch_ms")]
+  EpochMs,
+  #[value(name = "epoch_s")]
+  EpochS,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum PipeFormat {
+  /// One JSON document per line
+  Json,
+  /// Single JSON array printed after the last sample (requires --samples)
+  JsonArray,
+  /// InfluxDB line protocol
+  Influx,
+  /// One short line for status bars, updated in place in terminal with --samples 0
+  Status,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum StatusPart {
+  /// CPU usage and power, "CPU 12% 3.2W"
+  Cpu,
+  /// GPU usage and power, "GPU 4% 0.4W"
+  Gpu,
+  /// all_power, "8.1W"
+  Power,
+  /// System power, "Sys 12.0W"
+  Sys,
+  /// Average CPU temperature, "52°C"
+  Temp,
+  /// RAM usage, "RAM 12.3/16GB"
+  Ram,
+}
+
+#[derive(Debug, Args)]
+struct PipeArgs {
+  /// Number of samples to run for. Set to 0 to run indefinitely
+  #[arg(short, long, default_value_t = 0)]
+  samples: u32,
+
+  /// Stop after this wall-clock time, e.g. 30s, 5m, 1h30m (with --samples first limit wins)
+  #[arg(short, long, value_parser = parse_duration)]
+  duration: Option<std::time::Duration>,
+
+  /// Output format
+  #[arg(long, value_enum, default_value_t = PipeFormat::Json)]
+  format: PipeFormat,
+
+  /// Parts of --format status line in this order
+  #[arg(long, value_enum, value_delimiter = ',', default_value = "cpu,gpu,power,temp")]
+  status_parts: Vec<StatusPart>,
+
+  /// Timestamp format of each sample
+  #[arg(long, value_enum, default_value_t = TimestampFormat::Rfc3339)]
+  timestamp: TimestampFormat,
+
+  /// Include min / avg / max / p50 / p95 power stats over the last 128 samples
+  #[arg(long, default_value_t = false)]
+  stats: bool,
+
+  /// Print session energy summary to stderr on exit
+  #[arg(long, default_value_t = false)]
+  summary: bool,
+
+  /// Same as --summary, but in JSON format
+  #[arg(long, default_value_t = false)]
+  summary_json: bool,
+
+  /// Number of work units done during session (e.g. inferences) to report energy per unit
+  #[arg(long)]
+  work_units: Option<u32>,
+
+  /// Collect and output only these metrics, e.g. cpu_power,gpu_power,ram (default: all)
+  #[arg(long, value_delimiter = ',')]
+  metrics: Vec<String>,
+
+  /// Read temperature sensors only every N samples (temperature changes slowly)
+  #[arg(long, default_value_t = 1)]
+  temp_every: u32,
+
+  /// Number of sub-samples averaged per interval (1 – raw values, as powermetrics)
+  #[arg(long, default_value_t = 1)]
+  measures: u32,
+
+  /// Temperature source: auto (SMC if available, otherwise HID), smc, hid or merged
+  #[arg(long, value_enum, default_value_t = TempSource::Auto)]
+  temp_source: TempSource,
+
+  /// Residency states counted as idle, e.g. IDLE,DOWN,OFF (default: built-in list)
+  #[arg(long, value_delimiter = ',')]
+  idle_states: Vec<String>,
+
+  /// Output {"timestamp": ..., "error": "..."} record and continue if sample failed
+  #[arg(long, default_value_t = false)]
+  continue_on_error: bool,
+
+  /// Add cpu_power_per_core / gpu_power_per_core to compare efficiency across chips
+  #[arg(long, default_value_t = false)]
+  per_core: bool,
+
+  /// Add raw energy of each sample in millijoules (*_energy_mj) next to Watts
+  #[arg(long, default_value_t = false)]
+  energy: bool,
+
+  /// Read lines from stdin and output them as {"timestamp": ..., "marker": "..."} records
+  #[arg(long, default_value_t = false)]
+  markers: bool,
+
+  /// Output {"macmon_version": ..., "interval_ms": ..., "soc": {...}} line before samples
+  #[arg(long, default_value_t = false)]
+  header: bool,
+}
 
 #[derive(Debug, Subcommand)]
 enum Commands {
   /// Output metrics in JSON format (suitable for piping)
   #[command(alias = "raw")]
-  Pipe {
-    /// Number of samples to run for. Set to 0 to run indefinitely
-    #[arg(short, long, default_value_t = 0)]
-    samples: u32,
-  },
+  Pipe(PipeArgs),
 
   /// Print debug information
-  Debug,
+  Debug {
+    /// Show only N hottest sensors
+    #[arg(short = 'n', long)]
+    top_n: Option<usize>,
+
+    /// Show only sensors which name contains this pattern
+    #[arg(short, long)]
+    filter: Option<String>,
+
+    /// Order of HID sensors: name, value (hottest first) or kind (E-CPU, P-CPU, GPU, other)
+    #[arg(long, value_enum, default_value_t = SensorOrder::Name)]
+    sort: SensorOrder,
+
+    /// Show only HID sensors which name starts with this prefix, e.g. "pACC"
+    #[arg(long)]
+    prefix: Option<String>,
+
+    /// Dump all channels and sensors as JSON (for bug reports)
+    #[arg(long)]
+    json: bool,
+
+    /// List temperature sensors with their E-CPU / P-CPU / GPU group (from config prefixes)
+    #[arg(long)]
+    classify: bool,
+
+    /// Measure startup time with and without SMC keys cache
+    #[arg(long)]
+    startup: bool,
+  },
+
+  /// Print chip info (cores, memory, frequencies)
+  Info {
+    /// Print in JSON format
+    #[arg(long)]
+    json: bool,
+  },
+
+  /// Monitor several remote machines in one dashboard
+  Monitor {
+    /// Comma separated list of host:port serving latest pipe document at /json
+    #[arg(long, value_delimiter = ',', required = true)]
+    remote: Vec<String>,
+  },
+
+  /// Watch metrics and alert when condition holds (exit code 2 or webhook)
+  Watch {
+    /// Condition like cpu_temp>90 or all_power>=30, can be repeated
+    #[arg(long = "alert", required = true)]
+    alerts: Vec<watch::Alert>,
+
+    /// Number of seconds condition should hold before alert
+    #[arg(long, default_value_t = 0)]
+    hold: u64,
+
+    /// POST alert as JSON to this http:// URL and keep watching, instead of exit
+    #[arg(long)]
+    webhook: Option<String>,
+  },
+
+  /// Push metrics to OpenTelemetry collector (OTLP/HTTP with JSON encoding)
+  #[cfg(feature = "otlp")]
+  Otlp {
+    /// Collector metrics endpoint, only http:// is supported
+    #[arg(long, default_value = "http://localhost:4318/v1/metrics")]
+    endpoint: String,
+  },
+
+  /// Serve metrics over HTTP: /json (latest), /history?window=5m, /metrics (Prometheus)
+  #[cfg(feature = "serve")]
+  Serve {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:9090")]
+    bind: String,
+
+    /// How long samples are kept for /history, e.g. 15m, 1h
+    #[arg(long, default_value = "1h", value_parser = parse_duration)]
+    keep: std::time::Duration,
+  },
+
+  /// Write pipe output to date-stamped files in directory, rotated by size
+  Log {
+    /// Directory for log files, created if missing
+    #[arg(long)]
+    dir: std::path::PathBuf,
+
+    /// Start new file when current one reaches this size, e.g. 100MB, 1GB (0 – never)
+    #[arg(long, default_value = "100MB", value_parser = parse_size)]
+    rotate: u64,
+
+    /// Compress rotated files with gzip
+    #[arg(long, default_value_t = false)]
+    gzip: bool,
+
+    #[command(flatten)]
+    args: PipeArgs,
+  },
+
+  /// Print JSON Schema of pipe output
+  Schema,
+
+  /// Show top processes by energy usage
+  Top {
+    /// Number of processes to show
+    #[arg(short = 'n', long, default_value_t = 10)]
+    count: usize,
+  },
+
+  /// Watch raw SMC keys values
+  Smc {
+    /// Comma-separated list of SMC keys, e.g. TC0P,TG0P,PSTR
+    #[arg(short, long, value_delimiter = ',', required = true)]
+    keys: Vec<String>,
+  },
 }
 
 /// Sudoless performance monitoring CLI tool for Apple Silicon processors
@@ -31,33 +274,518 @@ struct Cli {
   #[command(subcommand)]
   command: Option<Commands>,
 
-  /// Update interval in milliseconds
-  #[arg(short, long, global = true, default_value_t = 1000)]
+  /// Update interval in milliseconds (100..10000)
+  #[arg(short, long, global = true, default_value_t = 1000, value_parser = parse_interval)]
+  interval: u32,
+
+  /// Use compact layout (enabled automatically for small terminals)
+  #[arg(long, default_value_t = false)]
+  compact: bool,
+
+  /// Fixed max of power charts in Watts (e.g. chip TDP) instead of auto-scale
+  #[arg(long)]
+  power_max: Option<f32>,
+
+  /// Desktop notification when condition like cpu_temp>90 starts to hold, can be repeated
+  #[arg(long = "notify")]
+  notify: Vec<watch::Alert>,
+
+  /// Apply named profile from config at startup (cycle with 'P' key)
+  #[arg(long)]
+  profile: Option<String>,
+
+  /// Carbon intensity of electricity in gCO2/kWh to estimate emissions (default: from config)
+  #[arg(long, global = true)]
+  carbon_intensity: Option<f32>,
+
+  /// Power values summed to all_power: cpu, gpu, ane, ram, gpu_ram [default: cpu,gpu,ane]
+  #[arg(long, global = true, value_enum, value_delimiter = ',')]
+  all_power: Vec<PowerPart>,
+
+  /// Path to config file [default: ~/.config/macmon.json]
+  #[arg(long, global = true)]
+  config: Option<String>,
+
+  /// Disable colors (also enabled by NO_COLOR env variable)
+  #[arg(long, global = true, default_value_t = false)]
+  no_color: bool,
+
+  /// Use only ASCII characters (enabled automatically for non UTF-8 locale)
+  #[arg(long, global = true, default_value_t = false)]
+  ascii: bool,
+}
+
+// 0 is rejected as likely mistake, other values are clamped to supported range
+fn parse_interval(val: &str) -> Result<u32, String> {
+  match val.parse::<u32>() {
+    Ok(0) => Err("interval must be greater than 0".to_string()),
+    Ok(x) => Ok(config::clamp_interval(x)),
+    Err(e) => Err(e.to_string()),
+  }
+}
+
+// size like "100MB", "512KB", "1GB" (powers of 1024); plain number is bytes
+fn parse_size(val: &str) -> Result<u64, String> {
+  let err = || format!("invalid size: {} (expected like 100MB, 1GB)", val);
+  let val = val.trim().to_uppercase();
+  let (num, unit) = match val.find(|c: char| !c.is_ascii_digit()) {
+    Some(idx) => val.split_at(idx),
+    None => (val.as_str(), ""),
+  };
+
+  let unit: u64 = match unit.trim() {
+    "" | "B" => 1,
+    "K" | "KB" => 1024,
+    "M" | "MB" => 1024 * 1024,
+    "G" | "GB" => 1024 * 1024 * 1024,
+    _ => return Err(err()),
+  };
+
+  num.parse::<u64>().map(|x| x * unit).map_err(|_| err())
+}
+
+// humantime-like duration: "500ms", "30s", "5m", "1h30m"; plain number is seconds
+fn parse_duration(val: &str) -> Result<std::time::Duration, String> {
+  let err = || format!("invalid duration: {} (expected like 30s, 5m, 1h30m)", val);
+  if let Ok(secs) = val.parse::<u64>() {
+    return Ok(std::time::Duration::from_secs(secs));
+  }
+
+  let (mut msec, mut num) = (0u64, String::new());
+  let mut chars = val.trim().chars().peekable();
+  while let Some(c) = chars.next() {
+    if c.is_ascii_digit() {
+      num.push(c);
+      continue;
+    }
+
+    let unit = match (c, chars.peek()) {
+      ('m', Some('s')) => {
+        chars.next();
+        1
+      }
+      ('h', _) => 3600 * 1000,
+      ('m', _) => 60 * 1000,
+      ('s', _) => 1000,
+      _ => return Err(err()),
+    };
+
+    msec += num.parse::<u64>().map_err(|_| err())? * unit;
+    num.clear();
+  }
+
+  if !num.is_empty() || msec == 0 {
+    return Err(err());
+  }
+
+  Ok(std::time::Duration::from_millis(msec))
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(untagged)]
+enum Timestamp {
+  Text(String),
+  Number(i64),
+}
+
+impl Timestamp {
+  fn now(format: TimestampFormat) -> Self {
+    Self::at(Utc::now(), format)
+  }
+
+  fn at(now: DateTime<Utc>, format: TimestampFormat) -> Self {
+    match format {
+      TimestampFormat::Rfc3339 => Self::Text(now.to_rfc3339_opts(SecondsFormat::Millis, true)),
+      TimestampFormat::EpochMs => Self::Number(now.timestamp_millis()),
+      TimestampFormat::EpochS => Self::Number(now.timestamp()),
+    }
+  }
+}
+
+// document printed on each line in pipe mode
+#[derive(Debug, Serialize, JsonSchema)]
+struct PipeDoc {
+  timestamp: Timestamp,
+  #[serde(flatten)]
+  metrics: Metrics,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  stats: Option<PowerStats>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  co2_grams: Option<f32>, // cumulative over session
+  #[serde(skip_serializing_if = "Option::is_none")]
+  cpu_power_per_core: Option<f32>, // Watts, E- and P-cores together
+  #[serde(skip_serializing_if = "Option::is_none")]
+  gpu_power_per_core: Option<f32>, // Watts
+}
+
+// fields of pipe output selectable with --metrics, timestamp and stats are set by own flags
+fn pipe_metric_names() -> Vec<String> {
+  let names = metrics::schema_fields(&schemars::schema_for!(PipeDoc));
+  names.into_iter().filter(|x| x != "timestamp" && x != "stats").collect()
+}
+
+// MARK: Influx
+
+// spaces, commas and equal signs must be escaped in tag values
+fn influx_escape(val: &str) -> String {
+  val.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+fn to_influx(tags: &str, doc: &serde_json::Value) -> String {
+  let fields =
+    flat_fields(doc).into_iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>();
+
+  let ts = chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0);
+  format!("macmon,{} {} {}", tags, fields.join(","), ts)
+}
+
+// markers are own measurement with string field, so they do not mix with numeric fields
+fn to_influx_marker(tags: &str, text: &str, time: DateTime<Utc>) -> String {
+  let text = text.replace('\\', "\\\\").replace('"', "\\\"");
+  let ts = time.timestamp_nanos_opt().unwrap_or(0);
+  format!("macmon_marker,{} marker=\"{}\" {}", tags, text, ts)
+}
+
+// MARK: Status
+
+// e.g. "CPU 12% 3.2W · GPU 4% 0.4W · 8.1W · 52°C", cpu usage is weighted by cores count
+fn to_status(m: &Metrics, parts: &[StatusPart], cores: (f32, f32)) -> String {
+  let pct = |x: f32| (x * 100.0).round() as u32;
+  let cpu_usage = zero_div(m.ecpu_usage.1 * cores.0 + m.pcpu_usage.1 * cores.1, cores.0 + cores.1);
+  let gb = |x: u64| x as f64 / 1024.0 / 1024.0 / 1024.0;
+
+  let items = parts.iter().map(|x| match x {
+    StatusPart::Cpu => format!("CPU {}% {:.1}W", pct(cpu_usage), m.cpu_power),
+    StatusPart::Gpu => format!("GPU {}% {:.1}W", pct(m.gpu_usage.1), m.gpu_power),
+    StatusPart::Power => format!("{:.1}W", m.all_power),
+    StatusPart::Sys => format!("Sys {:.1}W", m.sys_power),
+    StatusPart::Temp => format!("{:.0}°C", m.temp.cpu_temp_avg),
+    StatusPart::Ram => {
+      format!("RAM {:.1}/{:.0}GB", gb(m.memory.ram_usage), gb(m.memory.ram_total))
+    }
+  });
+
+  items.collect::<Vec<_>>().join(" · ")
+}
+
+// MARK: Pipe
+
+// stdin lines with time they were read, so markers keep position between samples
+fn run_markers_thread() -> std::sync::mpsc::Receiver<(DateTime<Utc>, String)> {
+  let (tx, rx) = std::sync::mpsc::channel();
+  std::thread::spawn(move || {
+    for line in std::io::stdin().lines() {
+      let line = match line {
+        Ok(line) => line,
+        Err(_) => break,
+      };
+
+      if !line.trim().is_empty() && tx.send((Utc::now(), line)).is_err() {
+        break;
+      }
+    }
+  });
+
+  rx
+}
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_stop_signal(_: libc::c_int) {
+  STOP.store(true, Ordering::SeqCst);
+}
+
+fn run_pipe(
+  args: &PipeArgs,
   interval: u32,
+  carbon: Option<f32>,
+  power_parts: &[PowerPart],
+  prefixes: SensorPrefixes,
+  out: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+  if matches!(args.format, PipeFormat::JsonArray) && args.samples == 0 && args.duration.is_none() {
+    return Err("--format json-array requires --samples > 0 or --duration".into());
+  }
+
+  if args.header && !matches!(args.format, PipeFormat::Json) {
+    return Err("--header requires --format json".into());
+  }
+
+  // finish current line on SIGINT / SIGTERM, so downstream parsers never get partial JSON
+  let handler = on_stop_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+  unsafe {
+    libc::signal(libc::SIGINT, handler);
+    libc::signal(libc::SIGTERM, handler);
+  }
+
+  let filter = MetricsFilter::with_known(&args.metrics, &pipe_metric_names())?;
+  let mut sampler = Sampler::new()?;
+  sampler.set_filter(filter.clone());
+  sampler.set_temp_every(args.temp_every);
+  sampler.set_measures(args.measures);
+  sampler.set_temp_source(args.temp_source);
+  sampler.set_power_parts(power_parts);
+  sampler.set_energy(args.energy);
+  sampler.set_idle_states(&args.idle_states);
+  sampler.set_sensor_prefixes(prefixes);
+
+  // counters are read at start, so the value does not include this session load
+  let boot_power = match args.summary || args.summary_json || args.work_units.is_some() {
+    true => get_boot_power(power_parts),
+    false => None,
+  };
+
+  let mut history = PowerHistory::default();
+  let mut energy = EnergyCounter::default();
+  let mut counter = 0u32;
+
+  let soc = sampler.soc();
+  let cpu_cores = (soc.ecpu_cores + soc.pcpu_cores) as f32;
+  let gpu_cores = soc.gpu_cores as f32;
+  let status_cores = (soc.ecpu_cores as f32, soc.pcpu_cores as f32);
+  let per_core = |power: f32, cores: f32| args.per_core.then(|| zero_div(power, cores));
+
+  let tags = match args.format {
+    PipeFormat::Influx => {
+      format!("chip={},model={}", influx_escape(&soc.chip_name), influx_escape(&soc.mac_model))
+    }
+    _ => String::new(),
+  };
+
+  // capture context for shared logs, sampling parameters as they were applied
+  if args.header {
+    let header = serde_json::json!({
+      "macmon_version": env!("CARGO_PKG_VERSION"),
+      "interval_ms": interval,
+      "measures": args.measures,
+      "metrics": args.metrics,
+      "units": units_json(),
+      "soc": soc,
+    });
+    writeln!(out, "{}", serde_json::to_string(&header)?)?;
+  }
+
+  let mut buffer = Vec::new();
+  let markers = if args.markers { Some(run_markers_thread()) } else { None };
+  let deadline = args.duration.map(|x| std::time::Instant::now() + x);
+
+  // endless status in terminal is redrawn on the same line, otherwise line per sample as usual
+  let is_status = matches!(args.format, PipeFormat::Status);
+  let in_place = is_status && args.samples == 0 && std::io::stdout().is_terminal();
+  let mut status = String::new();
+
+  loop {
+    let started = std::time::Instant::now();
+
+    // last sample is shorter to stop exactly on deadline
+    let msec = match deadline {
+      Some(x) => interval.min(x.saturating_duration_since(started).as_millis() as u32),
+      None => interval,
+    };
+
+    if msec == 0 {
+      break;
+    }
+
+    let mut doc = match sampler.get_metrics(msec) {
+      Ok(metrics) => {
+        energy.push(session_power(&metrics), started.elapsed().as_millis() as u64);
+        let stats = if args.stats { Some(history.push(&metrics)) } else { None };
+        if is_status {
+          status = to_status(&metrics, &args.status_parts, status_cores);
+        }
+
+        let timestamp = Timestamp::now(args.timestamp);
+        let co2_grams = carbon.map(|x| energy.co2_grams(x));
+        let cpu_power_per_core = per_core(metrics.cpu_power, cpu_cores);
+        let gpu_power_per_core = per_core(metrics.gpu_power, gpu_cores);
+        let doc =
+          PipeDoc { timestamp, metrics, stats, co2_grams, cpu_power_per_core, gpu_power_per_core };
+
+        let mut doc = serde_json::to_value(doc)?;
+        filter.apply(&mut doc);
+        doc
+      }
+      // error record instead of exit, so consumers can skip it and keep reading
+      Err(e) if args.continue_on_error => {
+        serde_json::json!({ "timestamp": Timestamp::now(args.timestamp), "error": e.to_string() })
+      }
+      Err(e) => return Err(e),
+    };
+
+    // markers received during sample go before it, output stays ordered by time
+    let mut lines = Vec::new();
+    for (time, text) in markers.iter().flat_map(|x| x.try_iter()) {
+      let doc =
+        serde_json::json!({ "timestamp": Timestamp::at(time, args.timestamp), "marker": text });
+      match args.format {
+        PipeFormat::Json => lines.push(serde_json::to_string(&doc)?),
+        PipeFormat::Influx => lines.push(to_influx_marker(&tags, &text, time)),
+        PipeFormat::JsonArray => buffer.push(doc),
+        PipeFormat::Status => {} // no place for markers in status line
+      }
+    }
+
+    let line = match args.format {
+      PipeFormat::Json => serde_json::to_string(&doc)?,
+      // line protocol and status have no place for errors
+      PipeFormat::Influx | PipeFormat::Status if doc.get("error").is_some() => {
+        eprintln!("{}", doc);
+        String::new()
+      }
+      PipeFormat::Influx => {
+        doc.as_object_mut().map(|x| x.remove("timestamp")); // own timestamp in ns
+        to_influx(&tags, &doc)
+      }
+      PipeFormat::JsonArray => {
+        buffer.push(doc);
+        String::new()
+      }
+      PipeFormat::Status if in_place => {
+        write!(out, "\r{}\x1b[K", status)?; // back to line start, then clear rest of line
+        String::new()
+      }
+      PipeFormat::Status => status.clone(),
+    };
+
+    lines.push(line);
+    for line in lines.iter().filter(|x| !x.is_empty()) {
+      writeln!(out, "{}", line)?;
+    }
+    out.flush()?;
+
+    counter += 1;
+    if STOP.load(Ordering::SeqCst) || (args.samples > 0 && counter >= args.samples) {
+      break;
+    }
+  }
+
+  if in_place {
+    writeln!(out)?;
+  }
+
+  // printed also on early stop, so output is always valid JSON
+  if matches!(args.format, PipeFormat::JsonArray) {
+    writeln!(out, "{}", serde_json::to_string(&buffer)?)?;
+    out.flush()?;
+  }
+
+  let mut summary = energy.summary(args.work_units, carbon);
+  summary.boot_avg_power = boot_power;
+  if args.summary_json {
+    eprintln!("{}", serde_json::to_string(&summary)?);
+  } else if args.summary || args.work_units.is_some() {
+    eprintln!("{}", summary);
+  }
+
+  Ok(())
+}
+
+// see: https://no-color.org
+fn is_no_color(args: &Cli) -> bool {
+  args.no_color || std::env::var("NO_COLOR").is_ok_and(|x| !x.is_empty())
+}
+
+// locale is taken from first set variable, as in libc; unknown locale is treated as UTF-8
+fn is_ascii(args: &Cli) -> bool {
+  let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+    .iter()
+    .filter_map(|x| std::env::var(x).ok())
+    .find(|x| !x.is_empty());
+
+  let utf8 = |x: &str| x.to_lowercase().replace('-', "").contains("utf8");
+  args.ascii || locale.is_some_and(|x| !utf8(&x))
+}
+
+// from flag, otherwise from config
+fn power_parts(args: &Cli, cfg: &config::Config) -> Vec<PowerPart> {
+  match args.all_power.is_empty() {
+    true => cfg.all_power.clone(),
+    false => args.all_power.clone(),
+  }
+}
+
+// from flag, otherwise from config; disabled when 0
+fn carbon_intensity(args: &Cli, cfg: &config::Config) -> Option<f32> {
+  let val = args.carbon_intensity.unwrap_or(cfg.carbon_intensity);
+  Some(val).filter(|x| *x > 0.0)
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
   let args = Cli::parse();
 
+  // info still works under Rosetta to show it, everything else needs native process
+  if !matches!(args.command, Some(Commands::Info { .. })) {
+    if let Err(e) = sources::check_native() {
+      eprintln!("{}", e);
+      std::process::exit(1);
+    }
+  }
+
+  // read once, as fallback for flags and CPU / GPU sensor prefixes (config only)
+  let cfg = config::Config::load(args.config.as_deref());
+
   match &args.command {
-    Some(Commands::Pipe { samples }) => {
-      let mut sampler = Sampler::new()?;
-      let mut counter = 0u32;
-
-      loop {
-        let doc = sampler.get_metrics(args.interval.max(100))?;
-        let doc = serde_json::to_string(&doc)?;
-        println!("{}", doc);
-
-        counter += 1;
-        if *samples > 0 && counter >= *samples {
-          break;
-        }
-      }
+    Some(Commands::Pipe(pipe_args)) => {
+      let out = &mut std::io::stdout().lock();
+      let (carbon, prefixes) = (carbon_intensity(&args, &cfg), cfg.sensor_prefixes());
+      run_pipe(pipe_args, args.interval, carbon, &power_parts(&args, &cfg), prefixes, out)?
     }
-    Some(Commands::Debug) => debug::print_debug()?,
+    Some(Commands::Log { dir, rotate, gzip, args: pipe_args }) => {
+      let ext = match pipe_args.format {
+        PipeFormat::Json => "jsonl",
+        PipeFormat::Influx => "lp",
+        // array is written only at exit, so it can't be rotated and is lost on crash
+        PipeFormat::JsonArray => return Err("--format json-array is not supported by log".into()),
+        PipeFormat::Status => return Err("--format status is not supported by log".into()),
+      };
+
+      let out = &mut logfile::RotatingFile::new(dir, ext, *rotate, *gzip)?;
+      out.set_header(pipe_args.header);
+      let (carbon, prefixes) = (carbon_intensity(&args, &cfg), cfg.sensor_prefixes());
+      run_pipe(pipe_args, args.interval, carbon, &power_parts(&args, &cfg), prefixes, out)?
+    }
+    Some(Commands::Debug { json: true, .. }) => debug::print_debug_json()?,
+    Some(Commands::Debug { startup: true, .. }) => debug::print_startup()?,
+    Some(Commands::Debug { classify: true, filter, .. }) => {
+      debug::print_sensor_groups(&cfg.sensor_prefixes(), filter.as_deref())?
+    }
+    Some(Commands::Debug { top_n, filter, sort, prefix, .. }) => {
+      let prefixes = cfg.sensor_prefixes();
+      debug::print_debug(*top_n, filter.as_deref(), *sort, prefix.as_deref(), &prefixes)?
+    }
+    Some(Commands::Info { json }) => debug::print_info(*json)?,
+    Some(Commands::Monitor { remote }) => {
+      let config = args.config.as_deref();
+      app::run_monitor(remote, args.interval, config, is_no_color(&args), is_ascii(&args))?
+    }
+    Some(Commands::Watch { alerts, hold, webhook }) => {
+      watch::run_watch(alerts, *hold, webhook.as_deref(), args.interval, &power_parts(&args, &cfg))?
+    }
+    #[cfg(feature = "otlp")]
+    Some(Commands::Otlp { endpoint }) => {
+      otlp::run_otlp(endpoint, args.interval, &power_parts(&args, &cfg))?
+    }
+    #[cfg(feature = "serve")]
+    Some(Commands::Serve { bind, keep }) => {
+      serve::run_serve(bind, *keep, args.interval, &power_parts(&args, &cfg))?
+    }
+    Some(Commands::Schema) => {
+      let mut schema = schemars::schema_for!(PipeDoc);
+      schema.schema.extensions.insert("x-units".to_string(), units_json());
+      println!("{}", serde_json::to_string_pretty(&schema)?);
+    }
+    Some(Commands::Top { count }) => top::print_top(*count, args.interval)?,
+    Some(Commands::Smc { keys }) => debug::print_smc_keys(keys, args.interval)?,
     _ => {
-      let mut app = App::new()?;
+      let mut app = App::new(args.config.as_deref())?;
+      if let Some(profile) = &args.profile {
+        app.set_profile(profile)?;
+      }
+      if !args.all_power.is_empty() {
+        app.set_power_parts(&args.all_power);
+      }
+      app.add_notify(&args.notify);
 
       let matches = Cli::command().get_matches();
       let msec = match matches.value_source("interval") {
@@ -65,7 +793,8 @@ fn main() -> Result<(), Box<dyn Error>> {
         _ => None,
       };
 
-      app.run_loop(msec)?;
+      let (no_color, ascii) = (is_no_color(&args), is_ascii(&args));
+      app.run_loop(msec, args.compact, no_color, ascii, args.power_max, args.carbon_intensity)?;
     }
   }
 
@@ -1,94 +1,484 @@
-use core_foundation::dictionary::CFDictionaryRef;
-use serde::Serialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use crate::sources::{
-  cfio_get_residencies, cfio_watts, libc_ram, libc_swap, IOHIDSensors, IOReport, SocInfo, SMC,
+  cfio_energy_mj, cfio_get_residencies, libc_mem_pressure, libc_ram, libc_ram_breakdown, libc_swap,
+  libc_uptime_ms, libc_vm_churn, pmset_power_mode, IOHIDSensors, IOReport, IOReportIterator,
+  IOReportIteratorItem, PLimitData, SensorGroup, SensorPrefixes, SocInfo, ThermalPressure, SMC,
 };
 
 type WithError<T> = Result<T, Box<dyn std::error::Error>>;
 
-// const CPU_FREQ_DICE_SUBG: &str = "CPU Complex Performance States";
+const CPU_FREQ_DICE_SUBG: &str = "CPU Complex Performance States";
 const CPU_FREQ_CORE_SUBG: &str = "CPU Core Performance States";
 const GPU_FREQ_DICE_SUBG: &str = "GPU Performance States";
+const POWER_MODE_TTL_SECS: u64 = 5;
 
 // MARK: Structs
 
-#[derive(Debug, Default, Serialize)]
+// where to read temperature from, auto – SMC if available, otherwise HID
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum TempSource {
+  #[default]
+  Auto,
+  Smc,
+  Hid,
+  Merged,
+}
+
+// power values summed to all_power
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum PowerPart {
+  Cpu,
+  Gpu,
+  Ane,
+  Ram,
+  #[value(name = "gpu_ram")]
+  GpuRam,
+}
+
+// SoC power without DRAM and GPU SRAM
+pub const DEFAULT_POWER_PARTS: &[PowerPart] = &[PowerPart::Cpu, PowerPart::Gpu, PowerPart::Ane];
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, JsonSchema)]
 pub struct TempMetrics {
-  pub cpu_temp_avg: f32, // Celsius
+  pub cpu_temp_avg: f32, // Celsius, SMC P-core ("Tp") or all HID CPU sensors
+  #[serde(default)]
+  pub ecpu_temp_avg: f32, // Celsius
+  #[serde(default)]
+  pub pcpu_temp_avg: f32, // Celsius
   pub gpu_temp_avg: f32, // Celsius
+  #[serde(default)]
+  pub pkg_temp: f32, // Celsius, max of CPU / GPU die SMC sensors (or configured one), 0 if none
 }
 
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct MemBreakdown {
+  pub wired_bytes: u64,
+  pub compressed_bytes: u64,
+  pub app_bytes: u64,
+  pub cached_bytes: u64,
+}
+
+// pages per second between samples, 0 on first sample
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct MemRates {
+  pub swapins: f32,
+  pub swapouts: f32,
+  pub compressions: f32,
+  pub decompressions: f32,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MemMetrics {
-  pub ram_total: u64,  // bytes
-  pub ram_usage: u64,  // bytes
-  pub swap_total: u64, // bytes
-  pub swap_usage: u64, // bytes
+  pub ram_total: u64,   // bytes
+  pub ram_usage: u64,   // bytes
+  pub swap_total: u64,  // bytes
+  pub swap_usage: u64,  // bytes
+  pub mem_pressure: u8, // 1 – normal, 2 – warn, 4 – critical
+  pub breakdown: MemBreakdown,
+  #[serde(default)]
+  pub rates: MemRates,
 }
 
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Metrics {
   pub temp: TempMetrics,
   pub memory: MemMetrics,
-  pub ecpu_usage: (u32, f32), // freq, percent_from_max
-  pub pcpu_usage: (u32, f32), // freq, percent_from_max
-  pub gpu_usage: (u32, f32),  // freq, percent_from_max
-  pub cpu_power: f32,         // Watts
-  pub gpu_power: f32,         // Watts
-  pub ane_power: f32,         // Watts
-  pub all_power: f32,         // Watts
-  pub sys_power: f32,         // Watts
-  pub ram_power: f32,         // Watts
-  pub gpu_ram_power: f32,     // Watts
+  pub ecpu_usage: (u32, f32),                 // freq, percent_from_max
+  pub pcpu_usage: (u32, f32),                 // freq, percent_from_max
+  pub gpu_usage: (u32, f32),                  // freq, percent_from_max
+  pub cluster_usage: Vec<(String, u32, f32)>, // cluster name, freq, percent_from_max
+  pub ecpu_idle_pct: f32,                     // idle residency, from 0 to 1
+  pub pcpu_idle_pct: f32,                     // idle residency, from 0 to 1
+  pub gpu_idle_pct: f32,                      // idle residency, from 0 to 1
+  pub cpu_power: f32,                         // Watts
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub ecpu_power: Option<f32>, // Watts, part of cpu_power, only if chip reports cluster energy
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub pcpu_power: Option<f32>, // Watts, ecpu_power + pcpu_power = cpu_power
+  pub gpu_power: f32,                         // Watts
+  pub ane_power: f32,                         // Watts
+  pub all_power: f32,                         // Watts
+  pub sys_power: f32,                         // Watts
+  pub sys_power_estimated: bool,              // no sensor, sys_power is SoC power + baseline
+  pub ram_power: f32,                         // Watts
+  pub gpu_ram_power: f32,                     // Watts
+  pub cpu_efficiency: f32,                    // (ecpu_usage + pcpu_usage) / cpu_power, per Watt
+  pub gpu_efficiency: f32,                    // gpu_usage / gpu_power, per Watt
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub cpu_energy_mj: Option<f32>, // raw energy over whole sample, only if enabled
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub gpu_energy_mj: Option<f32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub ane_energy_mj: Option<f32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub ram_energy_mj: Option<f32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub gpu_ram_energy_mj: Option<f32>,
+  pub throttling: bool, // thermal pressure above nominal
+  pub cpu_p_limit: u32, // SMC power limit
+  pub gpu_p_limit: u32, // SMC power limit
+  #[serde(default)]
+  pub mem_p_limit: u32, // SMC power limit
+  #[serde(default)]
+  pub power_mode: String, // "normal", "low" or "high" (Low / High Power Mode)
+  pub collection_ms: f32, // time of sample collection without sleep between readings
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, JsonSchema)]
+pub struct Stats {
+  pub min: f32,
+  pub avg: f32,
+  pub max: f32,
+  pub p50: f32,
+  pub p95: f32,
+}
+
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct PowerStats {
+  pub cpu_power: Stats,
+  pub gpu_power: Stats,
+  pub ane_power: Stats,
+  pub all_power: Stats,
+  pub sys_power: Stats,
+  pub ram_power: Stats,
+  pub gpu_ram_power: Stats,
+}
+
+// MARK: Units
+
+// units of output values by kind of field, same for pipe, serve and otlp output (temperature is
+// always Celsius, TUI temp_unit only changes display); used by `pipe --header` and `schema`
+pub const UNITS: &[(&str, &str)] = &[
+  ("power", "W"),              // *_power, *_power_per_core
+  ("energy", "mJ"),            // *_energy_mj
+  ("temp", "C"),               // temp.*
+  ("freq", "MHz"),             // first value of *_usage, cluster_usage
+  ("usage", "0..1"),           // second value of *_usage, *_idle_pct
+  ("memory", "bytes"),         // memory.*, except rates
+  ("memory_rates", "pages/s"), // memory.rates.*
+  ("collection_ms", "ms"),
+  ("co2_grams", "g"),
+];
+
+pub fn units_json() -> serde_json::Value {
+  let items = UNITS.iter().map(|(k, v)| (k.to_string(), serde_json::Value::from(*v)));
+  serde_json::Value::Object(items.collect())
 }
 
 // MARK: Helpers
 
+fn sum_power(m: &Metrics, parts: &[PowerPart]) -> f32 {
+  let parts = if parts.is_empty() { DEFAULT_POWER_PARTS } else { parts };
+  parts
+    .iter()
+    .map(|x| match x {
+      PowerPart::Cpu => m.cpu_power,
+      PowerPart::Gpu => m.gpu_power,
+      PowerPart::Ane => m.ane_power,
+      PowerPart::Ram => m.ram_power,
+      PowerPart::GpuRam => m.gpu_ram_power,
+    })
+    .sum()
+}
+
 pub fn zero_div<T: core::ops::Div<Output = T> + Default + PartialEq>(a: T, b: T) -> T {
   let zero: T = Default::default();
   return if b == zero { zero } else { a / b };
 }
 
-fn calc_freq(item: CFDictionaryRef, freqs: &Vec<u32>) -> (u32, f32) {
-  let items = cfio_get_residencies(item); // (ns, freq)
-  let (len1, len2) = (items.len(), freqs.len());
-  assert!(len1 > len2, "cacl_freq invalid data: {} vs {}", len1, len2); // todo?
+// nearest-rank percentile, items must be sorted
+fn percentile(sorted: &[f32], p: f32) -> f32 {
+  let rank = (p / 100.0 * sorted.len() as f32).ceil() as usize;
+  sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl Stats {
+  pub fn new(items: &[f32]) -> Self {
+    if items.is_empty() {
+      return Self::default();
+    }
+
+    let mut sorted = items.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+
+    Self {
+      min: sorted[0],
+      avg: sorted.iter().sum::<f32>() / sorted.len() as f32,
+      max: sorted[sorted.len() - 1],
+      p50: percentile(&sorted, 50.0),
+      p95: percentile(&sorted, 95.0),
+    }
+  }
+}
 
-  // IDLE / DOWN for CPU; OFF for GPU; DOWN only on M2?/M3 Max Chips
-  let offset = items.iter().position(|x| x.0 != "IDLE" && x.0 != "DOWN" && x.0 != "OFF").unwrap();
+// residency states without activity: IDLE / DOWN for CPU (DOWN only on M2?/M3 Max chips), OFF for
+// GPU; new chips may add own names, so list can be changed with Sampler::set_idle_states
+pub const IDLE_STATES: &[&str] = &["IDLE", "DOWN", "OFF"];
+
+// returns (avg freq of active states, fraction of peak throughput, idle ratio)
+// throughput is sum of residency shares weighted by freq / max_freq, so it is always in 0..1:
+// all idle – 0, all at max freq – 1, half idle & half at max freq – 0.5 (same as all at half freq)
+// items are (state name, residency) as from cfio_get_residencies, no IOReport calls here
+// unexpected layout (more states than freqs, empty DVFS table) gives 0 values instead of panic
+fn calc_freq(items: &[(String, i64)], freqs: &[u32], idle: &[String]) -> (u32, f32, f32) {
+  // leading idle states are skipped, rest are matched with freqs in order
+  let offset = items.iter().position(|x| !idle.contains(&x.0));
+  let offset = offset.unwrap_or(items.len()); // only idle states -> nothing active
 
   let usage = items.iter().map(|x| x.1 as f64).skip(offset).sum::<f64>();
   let total = items.iter().map(|x| x.1 as f64).sum::<f64>();
-  let count = freqs.len();
+  let count = freqs.len().min(items.len() - offset);
+  let max_freq = freqs.last().copied().unwrap_or(0) as f64;
 
   let mut avg_freq = 0f64;
+  let mut from_max = 0f64;
   for i in 0..count {
-    let percent = zero_div(items[i + offset].1 as _, usage);
-    avg_freq += percent * freqs[i] as f64;
+    let ns = items[i + offset].1 as f64;
+    avg_freq += zero_div(ns, usage) * freqs[i] as f64;
+    from_max += zero_div(ns, total) * zero_div(freqs[i] as f64, max_freq);
   }
 
-  let usage_ratio = zero_div(usage, total);
-  let min_freq = freqs.first().unwrap().clone() as f64;
-  let max_freq = freqs.last().unwrap().clone() as f64;
-  let from_max = (avg_freq.max(min_freq) * usage_ratio) / max_freq;
+  let idle_ratio = 1.0 - zero_div(usage, total); // residency in states before offset
+  (avg_freq as u32, from_max.clamp(0.0, 1.0) as f32, idle_ratio as f32)
+}
+
+// GPU DVFS table starts with 0 MHz entry for OFF state, which is already skipped by offset, so
+// active states are matched with non-zero freqs only. Fully idle GPU has no active residency and
+// reports its lowest freq (same as CPU in calc_freq_final), not 0 which skews averaged samples.
+fn calc_gpu_freq(items: &[(String, i64)], freqs: &[u32], idle: &[String]) -> (u32, f32, f32) {
+  let freqs = match freqs.iter().position(|x| *x > 0) {
+    Some(i) => &freqs[i..],
+    None => return (0, 0.0, 1.0),
+  };
 
-  (avg_freq as u32, from_max as f32)
+  let (freq, usage, idle) = calc_freq(items, freqs, idle);
+  (freq.max(freqs[0]), usage, idle)
 }
 
-fn calc_freq_final(items: &Vec<(u32, f32)>, freqs: &Vec<u32>) -> (u32, f32) {
+fn calc_freq_final(items: &[(u32, f32, f32)], freqs: &[u32]) -> (u32, f32, f32) {
   let avg_freq = zero_div(items.iter().map(|x| x.0 as f32).sum(), items.len() as f32);
   let avg_perc = zero_div(items.iter().map(|x| x.1 as f32).sum(), items.len() as f32);
-  let min_freq = freqs.first().unwrap().clone() as f32;
+  let avg_idle = zero_div(items.iter().map(|x| x.2).sum(), items.len() as f32);
+  let min_freq = freqs.first().copied().unwrap_or(0) as f32;
+
+  (avg_freq.max(min_freq) as u32, avg_perc, avg_idle)
+}
+
+// Ultra chips report some channels per die with "DIE_{}_" prefix, e.g. "DIE_1_CPU Energy"
+fn strip_die_prefix(channel: &str) -> &str {
+  match channel.strip_prefix("DIE_").and_then(|x| x.split_once('_')) {
+    Some((die, rest)) if die.chars().all(|c| c.is_ascii_digit()) => rest,
+    _ => channel,
+  }
+}
+
+// "Energy Model" on all known chips / OS versions, others are tried in case it is renamed
+const ENERGY_GROUPS: &[&str] = &["Energy Model", "PMP", "Power"];
+
+// group with CPU energy channel, detected once per process from list of all IOReport channels
+fn energy_group() -> &'static str {
+  static GROUP: std::sync::OnceLock<&'static str> = std::sync::OnceLock::new();
+  GROUP.get_or_init(|| {
+    let channels = IOReport::new(vec![]).map(|x| x.get_channels()).unwrap_or_default();
+    let has_energy = |name: &str| {
+      let mut items = channels.iter().filter(|(group, _, _)| group == name);
+      items.any(|(_, _, channel)| strip_die_prefix(channel).ends_with("CPU Energy"))
+    };
+
+    if let Some(group) = ENERGY_GROUPS.iter().find(|x| has_energy(x)) {
+      return group;
+    }
+
+    // all power is 0 in this case, so print what is available to make bug report actionable
+    let mut groups = channels.iter().map(|x| x.0.as_str()).collect::<Vec<_>>();
+    groups.sort();
+    groups.dedup();
+    eprintln!(
+      "Warning: no IOReport energy channels found (tried: {}), power values will be 0. \
+      Available groups: {}",
+      ENERGY_GROUPS.join(", "),
+      groups.join(", ")
+    );
+
+    ENERGY_GROUPS[0]
+  })
+}
 
-  (avg_freq.max(min_freq) as u32, avg_perc)
+fn add_energy(rs: &mut Metrics, x: &IOReportIteratorItem, dt: u64) -> WithError<()> {
+  add_channel_energy(rs, &x.channel, dt, || unsafe { cfio_energy_mj(x.item, &x.unit) })
 }
 
-fn init_smc() -> WithError<(SMC, Vec<String>, Vec<String>)> {
+// energy is read only for known channels, so unsupported units of other channels are not errors
+fn add_channel_energy(
+  rs: &mut Metrics,
+  channel: &str,
+  dt: u64,
+  read_mj: impl FnOnce() -> WithError<f32>,
+) -> WithError<()> {
+  // values from all dies are summed
+  let (power, energy) = match strip_die_prefix(channel) {
+    "GPU Energy" => (&mut rs.gpu_power, &mut rs.gpu_energy_mj),
+    // "CPU Energy" for Basic / Max, "DIE_{}_CPU Energy" for Ultra
+    c if c.ends_with("CPU Energy") => (&mut rs.cpu_power, &mut rs.cpu_energy_mj),
+    // same pattern next keys: "ANE" for Basic, "ANE0" for Max, "ANE0_{}" for Ultra
+    c if c.starts_with("ANE") => (&mut rs.ane_power, &mut rs.ane_energy_mj),
+    c if c.starts_with("DRAM") => (&mut rs.ram_power, &mut rs.ram_energy_mj),
+    c if c.starts_with("GPU SRAM") => (&mut rs.gpu_ram_power, &mut rs.gpu_ram_energy_mj),
+    // per cluster: "EACC_CPU", "PACC0_CPU", "PACC1_CPU", raw values until split_cpu_power
+    c if c.ends_with("_CPU") && (c.starts_with("EACC") || c.starts_with("PACC")) => {
+      let power = if c.starts_with('E') { &mut rs.ecpu_power } else { &mut rs.pcpu_power };
+      *power = Some(power.unwrap_or(0.0) + zero_div(read_mj()?, dt as f32));
+      return Ok(());
+    }
+    _ => return Ok(()),
+  };
+
+  let mj = read_mj()?;
+  *power += zero_div(mj, dt as f32); // mJ / ms = W
+  *energy = Some(energy.unwrap_or(0.0) + mj);
+  Ok(())
+}
+
+// cluster channels do not include shared CPU parts, so cpu_power is divided proportionally to them;
+// both None if chip has no separate E / P cluster energy
+fn split_cpu_power(rs: &mut Metrics) {
+  let (e, p) = match (rs.ecpu_power, rs.pcpu_power) {
+    (Some(e), Some(p)) => (e, p),
+    _ => {
+      (rs.ecpu_power, rs.pcpu_power) = (None, None);
+      return;
+    }
+  };
+
+  let ecpu_power = zero_div(e, e + p) * rs.cpu_power;
+  (rs.ecpu_power, rs.pcpu_power) = (Some(ecpu_power), Some(rs.cpu_power - ecpu_power));
+}
+
+// average of sub-samples, None if any of them has no value
+fn avg_some(items: impl Iterator<Item = Option<f32>>) -> Option<f32> {
+  let items = items.collect::<Option<Vec<_>>>()?;
+  Some(zero_div(items.iter().sum(), items.len() as f32))
+}
+
+// energy of sub-samples is summed, None unless raw energy enabled
+fn sum_energy(items: &[Metrics], enabled: bool, get: fn(&Metrics) -> Option<f32>) -> Option<f32> {
+  match enabled {
+    true => Some(items.iter().filter_map(get).sum()),
+    false => None,
+  }
+}
+
+// SMC temperature keys by kind
+#[derive(Debug, Default)]
+struct SmcKeys {
+  ecpu: Vec<String>,
+  pcpu: Vec<String>,
+  gpu: Vec<String>,
+  all: Vec<String>,
+}
+
+impl SmcKeys {
+  // classification is cheap and done on each start, only list of all keys is cached
+  fn new(all: Vec<String>, prefixes: &SensorPrefixes) -> Self {
+    let mut keys = SmcKeys::default();
+    for name in &all {
+      match prefixes.classify(name) {
+        Some(SensorGroup::Ecpu) => keys.ecpu.push(name.clone()),
+        Some(SensorGroup::Pcpu) => keys.pcpu.push(name.clone()),
+        Some(SensorGroup::Gpu) => keys.gpu.push(name.clone()),
+        None => (),
+      }
+    }
+
+    keys.all = all;
+    keys
+  }
+}
+
+// hottest of die sensors, out of range readings of unused sensors are skipped
+fn max_temp(vals: &[f32]) -> f32 {
+  vals.iter().copied().filter(|x| *x > 0.0 && *x < 150.0).fold(0.0, f32::max)
+}
+
+// averages of sensor values, combined CPU value is over own list to keep it same for old users
+fn temp_metrics(cpu: &[f32], ecpu: &[f32], pcpu: &[f32], gpu: &[f32]) -> TempMetrics {
+  let avg = |x: &[f32]| zero_div(x.iter().sum::<f32>(), x.len() as f32);
+
+  TempMetrics {
+    cpu_temp_avg: avg(cpu),
+    ecpu_temp_avg: avg(ecpu),
+    pcpu_temp_avg: avg(pcpu),
+    gpu_temp_avg: avg(gpu),
+    ..Default::default()
+  }
+}
+
+// ~/.cache/macmon/smc.json, key list is stable per machine and OS build, also checked by "#KEY"
+// count, so full enumeration (reads every SMC key, slowest part of startup) is done only once
+#[derive(Serialize, Deserialize)]
+struct SmcKeysCache {
+  key: String,
+  keys: Vec<String>,
+}
+
+impl SmcKeysCache {
+  fn read(path: &std::path::Path, key: &str) -> Option<Vec<String>> {
+    let cache: SmcKeysCache = serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()?;
+    (cache.key == key).then_some(cache.keys)
+  }
+
+  // errors are ignored, next run just scans keys again
+  fn write(path: &std::path::Path, key: String, keys: Vec<String>) -> Vec<String> {
+    if keys.is_empty() {
+      return keys; // do not keep failed scan
+    }
+
+    let _ = std::fs::create_dir_all(path.parent().unwrap());
+    let cache = SmcKeysCache { key, keys };
+    if let Ok(data) = serde_json::to_string(&cache) {
+      let _ = std::fs::write(path, data);
+    }
+
+    cache.keys
+  }
+}
+
+// all float temperature keys, classified later by SmcKeys::new
+fn init_smc() -> WithError<(SMC, Vec<String>)> {
   let mut smc = SMC::new()?;
 
-  let mut cpu_sensors = Vec::new();
-  let mut gpu_sensors = Vec::new();
+  let count = smc.key_count().ok();
+  let cache = crate::sources::cache_path("smc.json")
+    .zip(count.and_then(|n| crate::sources::cache_key().map(|x| format!("{} {}", x, n))));
+  if let Some(keys) = cache.as_ref().and_then(|(path, key)| SmcKeysCache::read(path, key)) {
+    return Ok((smc, keys));
+  }
+
+  let keys = scan_smc_keys(&mut smc);
+  let keys = match cache {
+    Some((path, key)) => SmcKeysCache::write(&path, key, keys),
+    None => keys,
+  };
+
+  Ok((smc, keys))
+}
+
+// (full key scan, start from cache) durations, for `macmon debug --startup`
+pub fn smc_startup_timings() -> WithError<(std::time::Duration, std::time::Duration)> {
+  let started = std::time::Instant::now();
+  scan_smc_keys(&mut SMC::new()?);
+  let cold = started.elapsed();
+
+  init_smc()?; // writes cache if it was missing, so next open is warm
+  let started = std::time::Instant::now();
+  init_smc()?;
+  Ok((cold, started.elapsed()))
+}
+
+fn scan_smc_keys(smc: &mut SMC) -> Vec<String> {
+  let mut keys = Vec::new();
 
   let names = smc.read_all_keys().unwrap_or(vec![]);
   for name in &names {
@@ -106,18 +496,276 @@ fn init_smc() -> WithError<(SMC, Vec<String>, Vec<String>)> {
       Err(_) => continue,
     };
 
-    // Unfortunately, it is not known which keys are responsible for what.
-    // Basically in the code that can be found publicly "Tp" is used for CPU and "Tg" for GPU.
+    // CPU / GPU keys and broader list for package temperature: die, SoC, etc.
+    if name.starts_with('T') {
+      keys.push(name.clone());
+    }
+  }
 
-    match name {
-      name if name.starts_with("Tp") => cpu_sensors.push(name.clone()),
-      name if name.starts_with("Tg") => gpu_sensors.push(name.clone()),
-      _ => (),
+  keys
+}
+
+// MARK: PowerHistory
+
+const STATS_WINDOW: usize = 128;
+
+// keeps last power values to calculate stats in pipe mode
+#[derive(Debug, Default)]
+pub struct PowerHistory {
+  items: Vec<[f32; 7]>,
+}
+
+impl PowerHistory {
+  pub fn push(&mut self, m: &Metrics) -> PowerStats {
+    let row = [
+      m.cpu_power,
+      m.gpu_power,
+      m.ane_power,
+      m.all_power,
+      m.sys_power,
+      m.ram_power,
+      m.gpu_ram_power,
+    ];
+
+    self.items.insert(0, row);
+    self.items.truncate(STATS_WINDOW);
+
+    let col = |i: usize| Stats::new(&self.items.iter().map(|x| x[i]).collect::<Vec<_>>());
+    PowerStats {
+      cpu_power: col(0),
+      gpu_power: col(1),
+      ane_power: col(2),
+      all_power: col(3),
+      sys_power: col(4),
+      ram_power: col(5),
+      gpu_ram_power: col(6),
+    }
+  }
+}
+
+// MARK: Energy
+
+#[derive(Debug, Default, Serialize)]
+pub struct EnergySummary {
+  pub duration_sec: f32,
+  pub samples: u32,
+  pub avg_power: f32,  // Watts
+  pub peak_power: f32, // Watts
+  pub energy_wh: f32,  // Watt-hours
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub energy_per_unit_j: Option<f32>, // Joules
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub co2_grams: Option<f32>, // estimated, by carbon intensity of electricity
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub boot_avg_power: Option<f32>, // Watts, SoC average since boot
+}
+
+impl std::fmt::Display for EnergySummary {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "Session {:.1}s ({} samples): {:.4} Wh, avg {:.2} W, peak {:.2} W",
+      self.duration_sec, self.samples, self.energy_wh, self.avg_power, self.peak_power
+    )?;
+
+    if let Some(x) = self.energy_per_unit_j {
+      write!(f, ", {:.4} J per work unit", x)?;
+    }
+
+    if let Some(x) = self.co2_grams {
+      write!(f, ", ~{:.3} g CO2", x)?;
+    }
+
+    if let Some(x) = self.boot_avg_power {
+      write!(f, ", since boot avg {:.2} W (SoC)", x)?;
+    }
+
+    Ok(())
+  }
+}
+
+// power counted into session energy and CO2 by TUI and pipe: system power if sensor available,
+// otherwise SoC only
+pub fn session_power(m: &Metrics) -> f32 {
+  match m.sys_power > 0.0 && !m.sys_power_estimated {
+    true => m.sys_power,
+    false => m.all_power,
+  }
+}
+
+// integrates power over session time
+#[derive(Debug, Default)]
+pub struct EnergyCounter {
+  samples: u32,
+  duration_ms: u64,
+  energy_j: f64,
+  peak_power: f32,
+}
+
+impl EnergyCounter {
+  pub fn push(&mut self, watts: f32, dt_ms: u64) {
+    self.samples += 1;
+    self.duration_ms += dt_ms;
+    self.energy_j += watts as f64 * dt_ms as f64 / 1000.0;
+    self.peak_power = self.peak_power.max(watts);
+  }
+
+  // carbon intensity in gCO2/kWh
+  pub fn co2_grams(&self, intensity: f32) -> f32 {
+    (self.energy_j / 3_600_000.0 * intensity as f64) as f32
+  }
+
+  pub fn summary(&self, work_units: Option<u32>, carbon_intensity: Option<f32>) -> EnergySummary {
+    let duration_sec = self.duration_ms as f64 / 1000.0;
+    let energy_per_unit_j = work_units.map(|x| zero_div(self.energy_j, x as f64) as f32);
+
+    EnergySummary {
+      duration_sec: duration_sec as f32,
+      samples: self.samples,
+      avg_power: zero_div(self.energy_j, duration_sec) as f32,
+      peak_power: self.peak_power,
+      energy_wh: (self.energy_j / 3600.0) as f32,
+      energy_per_unit_j,
+      co2_grams: carbon_intensity.map(|x| self.co2_grams(x)),
+      boot_avg_power: None,
+    }
+  }
+}
+
+// MARK: MetricsFilter
+
+// top-level properties of document schema, e.g. all serialized fields of Metrics
+pub fn schema_fields(schema: &schemars::schema::RootSchema) -> Vec<String> {
+  let props = schema.schema.object.as_ref().map(|x| x.properties.keys().cloned().collect());
+  props.unwrap_or_default()
+}
+
+// top-level fields of Metrics, used to validate --metrics values; taken from schema, so new
+// fields can be selected without updating separate list
+pub fn metric_names() -> &'static [String] {
+  static NAMES: std::sync::OnceLock<Vec<String>> = std::sync::OnceLock::new();
+  NAMES.get_or_init(|| schema_fields(&schemars::schema_for!(Metrics)))
+}
+
+// selected metrics to collect and output, everything if empty
+#[derive(Debug, Default, Clone)]
+pub struct MetricsFilter {
+  names: Vec<String>,
+  known: Vec<String>,
+}
+
+impl MetricsFilter {
+  pub fn new(names: &[String]) -> WithError<Self> {
+    Self::with_known(names, metric_names())
+  }
+
+  // for documents with own fields next to Metrics ones, e.g. co2_grams in pipe output
+  pub fn with_known(names: &[String], known: &[String]) -> WithError<Self> {
+    let mut rs = Vec::with_capacity(names.len());
+    for name in names {
+      let name = match name.trim() {
+        "ram" | "mem" => "memory",
+        "temps" => "temp",
+        x => x,
+      };
+
+      if !known.iter().any(|x| x == name) {
+        return Err(format!("Unknown metric: {} (available: {})", name, known.join(", ")).into());
+      }
+
+      rs.push(name.to_string());
+    }
+
+    Ok(Self { names: rs, known: known.to_vec() })
+  }
+
+  pub fn has(&self, name: &str) -> bool {
+    self.names.is_empty() || self.names.iter().any(|x| x == name)
+  }
+
+  // drop not selected metrics from serialized document, other keys are kept as is
+  pub fn apply(&self, doc: &mut serde_json::Value) {
+    if let Some(doc) = doc.as_object_mut() {
+      // flags like "sys_power_estimated" follow their metric
+      let follows = |k: &str| k.strip_suffix("_estimated").is_some_and(|x| self.has(x));
+      doc.retain(|k, _| !self.known.contains(k) || self.has(k) || follows(k));
     }
   }
+}
 
-  // println!("{} {}", cpu_sensors.len(), gpu_sensors.len());
-  Ok((smc, cpu_sensors, gpu_sensors))
+// MARK: Flat fields
+
+// scalar values of serialized document with nested keys joined by "_",
+// (freq, usage) tuples are split to "*_freq" and "*_usage", e.g. "ecpu_freq", "ecpu_usage"
+pub fn flat_fields(doc: &serde_json::Value) -> Vec<(String, serde_json::Value)> {
+  let mut rs = Vec::new();
+  add_flat_fields("", doc, &mut rs);
+  rs
+}
+
+fn add_flat_fields(
+  prefix: &str,
+  val: &serde_json::Value,
+  out: &mut Vec<(String, serde_json::Value)>,
+) {
+  use serde_json::Value;
+
+  match val {
+    Value::Number(_) | Value::Bool(_) => out.push((prefix.to_string(), val.clone())),
+    Value::Object(items) => {
+      for (k, v) in items {
+        let key = if prefix.is_empty() { k.clone() } else { format!("{}_{}", prefix, k) };
+        add_flat_fields(&key, v, out);
+      }
+    }
+    Value::Array(items) => match items.as_slice() {
+      [freq, usage] if freq.is_number() && usage.is_number() => {
+        let name = prefix.trim_end_matches("_usage");
+        add_flat_fields(&format!("{}_freq", name), freq, out);
+        add_flat_fields(&format!("{}_usage", name), usage, out);
+      }
+      // cluster_usage: [[name, freq, usage], ...]
+      _ => {
+        for item in items {
+          if let Some([Value::String(name), freq, usage]) = item.as_array().map(|x| x.as_slice()) {
+            let name = format!("{}_{}", prefix.trim_end_matches("_usage"), name.to_lowercase());
+            add_flat_fields(&format!("{}_freq", name), freq, out);
+            add_flat_fields(&format!("{}_usage", name), usage, out);
+          }
+        }
+      }
+    },
+    _ => {}
+  }
+}
+
+// rough power of the rest of system at idle (SSD, IO, fans, power supply loss), not measured
+// but picked from known desktops, only used when there is no system power sensor
+fn sys_baseline_watts(chip_name: &str) -> f32 {
+  match chip_name {
+    x if x.contains("Ultra") => 12.0,
+    x if x.contains("Max") => 8.0,
+    x if x.contains("Pro") => 6.0,
+    _ => 4.0,
+  }
+}
+
+// PSTR read can fail once in a while (SMC busy), so last measured value is kept for a few
+// samples; estimate only if sensor never worked or failed that many reads in a row
+const SYS_POWER_MAX_FAILS: u32 = 3;
+
+// state is (last measured watts, consecutive failures), None – use estimate
+fn sys_power_reading(state: &mut (f32, u32), read: Option<f32>) -> Option<f32> {
+  match read.filter(|x| *x > 0.0) {
+    Some(val) => {
+      *state = (val, 0);
+      Some(val)
+    }
+    None => {
+      state.1 += 1;
+      (state.0 > 0.0 && state.1 < SYS_POWER_MAX_FAILS).then_some(state.0)
+    }
+  }
 }
 
 // MARK: Sampler
@@ -127,143 +775,315 @@ pub struct Sampler {
   ior: IOReport,
   hid: IOHIDSensors,
   smc: SMC,
-  smc_cpu_keys: Vec<String>,
-  smc_gpu_keys: Vec<String>,
+  smc_keys: SmcKeys,
+  sensor_prefixes: SensorPrefixes,
+  pkg_temp_key: Option<String>,
+  thermal: Option<ThermalPressure>,
+  filter: MetricsFilter,
+  temp_every: u32,
+  temp_count: u32,
+  temp_last: TempMetrics,
+  measures: usize,
+  temp_source: TempSource,
+  power_parts: Vec<PowerPart>,
+  energy: bool,
+  power_mode: (String, Option<std::time::Instant>),
+  vm_churn: Option<([u64; 4], std::time::Instant)>,
+  idle_states: Vec<String>,
+  sys_power: (f32, u32),
 }
 
 impl Sampler {
   pub fn new() -> WithError<Self> {
     let channels = vec![
-      ("Energy Model", None), // cpu/gpu/ane power
-      // ("CPU Stats", Some(CPU_FREQ_DICE_SUBG)), // cpu freq by cluster
+      (energy_group(), None),                  // cpu/gpu/ane power
+      ("CPU Stats", Some(CPU_FREQ_DICE_SUBG)), // cpu freq by cluster
       ("CPU Stats", Some(CPU_FREQ_CORE_SUBG)), // cpu freq per core
       ("GPU Stats", Some(GPU_FREQ_DICE_SUBG)), // gpu freq
     ];
 
-    let soc = SocInfo::new()?;
+    let mut soc = SocInfo::new()?;
     let ior = IOReport::new(channels)?;
+    soc.set_clusters(&ior);
     let hid = IOHIDSensors::new()?;
-    let (smc, smc_cpu_keys, smc_gpu_keys) = init_smc()?;
+    let (smc, smc_keys) = init_smc()?;
+    let sensor_prefixes = SensorPrefixes::default();
+    let smc_keys = SmcKeys::new(smc_keys, &sensor_prefixes);
+    // throttling is optional indicator, sampler works without it (reported as false)
+    let thermal = ThermalPressure::new().ok();
 
-    Ok(Sampler { soc, ior, hid, smc, smc_cpu_keys, smc_gpu_keys })
+    Ok(Sampler {
+      soc,
+      ior,
+      hid,
+      smc,
+      smc_keys,
+      sensor_prefixes,
+      pkg_temp_key: None,
+      thermal,
+      filter: MetricsFilter::default(),
+      temp_every: 1,
+      temp_count: 0,
+      temp_last: TempMetrics::default(),
+      measures: 4,
+      temp_source: TempSource::Auto,
+      power_parts: DEFAULT_POWER_PARTS.to_vec(),
+      energy: false,
+      power_mode: (String::new(), None),
+      vm_churn: None,
+      idle_states: IDLE_STATES.iter().map(|x| x.to_string()).collect(),
+      sys_power: (0.0, 0),
+    })
   }
 
-  fn get_temp_smc(&mut self) -> WithError<TempMetrics> {
-    let mut cpu_metrics = Vec::new();
-    for sensor in &self.smc_cpu_keys {
-      let val = self.smc.read_val(sensor)?;
-      let val = f32::from_le_bytes(val.data[0..4].try_into().unwrap());
-      cpu_metrics.push(val);
-    }
+  pub fn set_temp_source(&mut self, source: TempSource) {
+    self.temp_source = source;
+  }
 
-    let mut gpu_metrics = Vec::new();
-    for sensor in &self.smc_gpu_keys {
-      let val = self.smc.read_val(sensor)?;
-      let val = f32::from_le_bytes(val.data[0..4].try_into().unwrap());
-      gpu_metrics.push(val);
-    }
+  // number of sub-samples averaged per interval, 1 – raw values (as powermetrics)
+  pub fn set_measures(&mut self, n: u32) {
+    self.measures = n.max(1) as usize;
+  }
+
+  // SMC key for pkg_temp (e.g. "Tp0C"), max of CPU / GPU die keys when not set
+  pub fn set_pkg_temp_key(&mut self, key: Option<&str>) {
+    self.pkg_temp_key = key.filter(|x| !x.is_empty()).map(|x| x.to_string());
+  }
 
-    let cpu_temp_avg = zero_div(cpu_metrics.iter().sum::<f32>(), cpu_metrics.len() as f32);
-    let gpu_temp_avg = zero_div(gpu_metrics.iter().sum::<f32>(), gpu_metrics.len() as f32);
+  // which SMC keys / HID sensors are averaged as E-CPU, P-CPU and GPU temperature
+  pub fn set_sensor_prefixes(&mut self, prefixes: SensorPrefixes) {
+    self.smc_keys = SmcKeys::new(std::mem::take(&mut self.smc_keys.all), &prefixes);
+    self.sensor_prefixes = prefixes;
+  }
+
+  // temperature changes slowly, so sensors can be read only every N samples
+  pub fn set_temp_every(&mut self, n: u32) {
+    self.temp_every = n.max(1);
+  }
 
-    Ok(TempMetrics { cpu_temp_avg, gpu_temp_avg })
+  // composition of all_power, default when empty
+  pub fn set_power_parts(&mut self, parts: &[PowerPart]) {
+    self.power_parts = parts.to_vec();
+  }
+
+  // raw *_energy_mj values next to Watts
+  pub fn set_energy(&mut self, enabled: bool) {
+    self.energy = enabled;
+  }
+
+  // names of residency states counted as idle, default IDLE_STATES when empty
+  pub fn set_idle_states(&mut self, names: &[String]) {
+    self.idle_states = match names.is_empty() {
+      true => IDLE_STATES.iter().map(|x| x.to_string()).collect(),
+      false => names.to_vec(),
+    };
+  }
+
+  pub fn set_filter(&mut self, filter: MetricsFilter) {
+    self.filter = filter;
+  }
+
+  pub fn soc(&self) -> &SocInfo {
+    &self.soc
+  }
+
+  fn get_temp_smc(&mut self) -> WithError<TempMetrics> {
+    let ecpu_metrics = self.smc.read_f32_each(&self.smc_keys.ecpu);
+    let pcpu_metrics = self.smc.read_f32_each(&self.smc_keys.pcpu);
+    let gpu_metrics = self.smc.read_f32_each(&self.smc_keys.gpu);
+    // combined value was always P-core keys only with SMC, "Te" keys are not mixed into it
+    let mut rs = temp_metrics(&pcpu_metrics, &ecpu_metrics, &pcpu_metrics, &gpu_metrics);
+    rs.pkg_temp = max_temp(&[ecpu_metrics, pcpu_metrics, gpu_metrics].concat());
+    Ok(rs)
   }
 
   fn get_temp_hid(&mut self) -> WithError<TempMetrics> {
     let metrics = self.hid.get_metrics();
 
-    let mut cpu_values = Vec::new();
+    let mut ecpu_values = Vec::new();
+    let mut pcpu_values = Vec::new();
     let mut gpu_values = Vec::new();
 
     for (name, value) in &metrics {
-      if name.starts_with("pACC MTR Temp Sensor") || name.starts_with("eACC MTR Temp Sensor") {
-        // println!("{}: {}", name, value);
-        cpu_values.push(*value);
-        continue;
-      }
-
-      if name.starts_with("GPU MTR Temp Sensor") {
-        // println!("{}: {}", name, value);
-        gpu_values.push(*value);
-        continue;
+      match self.sensor_prefixes.classify(name) {
+        Some(SensorGroup::Ecpu) => ecpu_values.push(*value),
+        Some(SensorGroup::Pcpu) => pcpu_values.push(*value),
+        Some(SensorGroup::Gpu) => gpu_values.push(*value),
+        None => {}
       }
     }
 
-    let cpu_temp_avg = zero_div(cpu_values.iter().sum(), cpu_values.len() as f32);
-    let gpu_temp_avg = zero_div(gpu_values.iter().sum(), gpu_values.len() as f32);
+    // HID combined value was always over all CPU sensors
+    let cpu_values = [ecpu_values.as_slice(), pcpu_values.as_slice()].concat();
+    Ok(temp_metrics(&cpu_values, &ecpu_values, &pcpu_values, &gpu_values))
+  }
 
-    Ok(TempMetrics { cpu_temp_avg, gpu_temp_avg })
+  // single "how hot is the chip" value; only die keys are used, as other T* keys are battery,
+  // ambient, palm rest, etc. – `from_smc` is value already taken from die keys by SMC source
+  fn get_pkg_temp(&mut self, from_smc: f32) -> f32 {
+    match &self.pkg_temp_key {
+      Some(key) => max_temp(&self.smc.read_f32_each(std::slice::from_ref(key))),
+      None if from_smc > 0.0 => from_smc,
+      None => {
+        let keys = &self.smc_keys;
+        let keys = [keys.ecpu.as_slice(), &keys.pcpu, &keys.gpu].concat();
+        max_temp(&self.smc.read_f32_each(&keys))
+      }
+    }
   }
 
   fn get_temp(&mut self) -> WithError<TempMetrics> {
     // HID for M1, SMC for M2/M3
     // UPD: Looks like HID/SMC related to OS version, not to the chip (SMC available from macOS 14)
-    match self.smc_cpu_keys.len() > 0 {
-      true => self.get_temp_smc(),
-      false => self.get_temp_hid(),
-    }
+    let mut rs = match self.temp_source {
+      TempSource::Auto if !self.smc_keys.pcpu.is_empty() => self.get_temp_smc(),
+      TempSource::Auto => self.get_temp_hid(),
+      TempSource::Smc => self.get_temp_smc(),
+      TempSource::Hid => self.get_temp_hid(),
+      TempSource::Merged => {
+        // some machines have better CPU coverage in SMC and GPU in HID (or vice versa)
+        let smc = self.get_temp_smc().unwrap_or_default();
+        let hid = self.get_temp_hid().unwrap_or_default();
+        let pick = |a: f32, b: f32| if a > 0.0 { a } else { b };
+        Ok(TempMetrics {
+          cpu_temp_avg: pick(smc.cpu_temp_avg, hid.cpu_temp_avg),
+          ecpu_temp_avg: pick(smc.ecpu_temp_avg, hid.ecpu_temp_avg),
+          pcpu_temp_avg: pick(smc.pcpu_temp_avg, hid.pcpu_temp_avg),
+          gpu_temp_avg: pick(smc.gpu_temp_avg, hid.gpu_temp_avg),
+          pkg_temp: smc.pkg_temp,
+        })
+      }
+    }?;
+
+    rs.pkg_temp = self.get_pkg_temp(rs.pkg_temp);
+    Ok(rs)
   }
 
   fn get_mem(&mut self) -> WithError<MemMetrics> {
     let (ram_usage, ram_total) = libc_ram()?;
     let (swap_usage, swap_total) = libc_swap()?;
-    Ok(MemMetrics { ram_total, ram_usage, swap_total, swap_usage })
+    let mem_pressure = libc_mem_pressure().unwrap_or(0);
+
+    let (wired_bytes, compressed_bytes, app_bytes, cached_bytes) = libc_ram_breakdown()?;
+    let breakdown = MemBreakdown { wired_bytes, compressed_bytes, app_bytes, cached_bytes };
+
+    let rates = self.get_mem_rates().unwrap_or_default();
+    Ok(MemMetrics { ram_total, ram_usage, swap_total, swap_usage, mem_pressure, breakdown, rates })
   }
 
-  fn get_sys_power(&mut self) -> WithError<f32> {
-    let val = self.smc.read_val("PSTR")?;
-    let val = f32::from_le_bytes(val.data.clone().try_into().unwrap());
-    Ok(val)
+  // swap and compressor churn from counters delta since previous call
+  fn get_mem_rates(&mut self) -> WithError<MemRates> {
+    let (next, now) = (libc_vm_churn()?, std::time::Instant::now());
+    let prev = self.vm_churn.replace((next, now));
+
+    let (prev, at) = match prev {
+      Some(x) => x,
+      None => return Ok(MemRates::default()),
+    };
+
+    let secs = now.duration_since(at).as_secs_f32();
+    let rate = |i: usize| zero_div(next[i].saturating_sub(prev[i]) as f32, secs);
+    Ok(MemRates {
+      swapins: rate(0),
+      swapouts: rate(1),
+      compressions: rate(2),
+      decompressions: rate(3),
+    })
   }
 
-  pub fn get_metrics(&mut self, duration: u32) -> WithError<Metrics> {
-    let measures: usize = 4;
-    let mut results: Vec<Metrics> = Vec::with_capacity(measures);
+  // pmset is external process, so mode is refreshed only every few seconds
+  fn get_power_mode(&mut self) -> String {
+    let (mode, read_at) = &mut self.power_mode;
+    if read_at.is_none_or(|x| x.elapsed().as_secs() >= POWER_MODE_TTL_SECS) {
+      *mode = pmset_power_mode().unwrap_or_default();
+      *read_at = Some(std::time::Instant::now());
+    }
 
-    // do several samples to smooth metrics
-    // see: https://github.com/vladkens/macmon/issues/10
-    for (sample, dt) in self.ior.get_samples(duration as u64, measures) {
-      let mut ecpu_usages = Vec::new();
-      let mut pcpu_usages = Vec::new();
-      let mut rs = Metrics::default();
+    mode.clone()
+  }
 
-      for x in sample {
-        if x.group == "CPU Stats" && x.subgroup == CPU_FREQ_CORE_SUBG {
-          if x.channel.contains("ECPU") {
-            ecpu_usages.push(calc_freq(x.item, &self.soc.ecpu_freqs));
-            continue;
-          }
+  // PSTR on laptops and most desktops, PDTR (DC-in power) on some machines without it
+  fn get_sys_power(&mut self) -> WithError<f32> {
+    match self.smc.read_val("PSTR").and_then(|x| x.decode_f32()) {
+      Ok(val) => Ok(val),
+      Err(_) => self.smc.read_val("PDTR")?.decode_f32(),
+    }
+  }
 
-          if x.channel.contains("PCPU") {
-            pcpu_usages.push(calc_freq(x.item, &self.soc.pcpu_freqs));
-            continue;
-          }
-        }
+  fn get_throttling(&mut self) -> (bool, PLimitData) {
+    // power limits not available on all machines and their nominal value is unknown, so they
+    // are exposed raw and only thermal pressure counts as throttling
+    let limits = self.smc.read_power_limits().unwrap_or_default();
+    let pressure = self.thermal.as_ref().and_then(|x| x.get_level().ok()).unwrap_or(0);
+    (pressure > 0, limits)
+  }
 
-        if x.group == "GPU Stats" && x.subgroup == GPU_FREQ_DICE_SUBG {
-          match x.channel.as_str() {
-            "GPUPH" => rs.gpu_usage = calc_freq(x.item, &self.soc.gpu_freqs[1..].to_vec()),
-            _ => {}
-          }
+  // usage & power of one IOReport delta sample, channel matching only (sampling is done by caller)
+  fn parse_sample(&self, sample: IOReportIterator, dt: u64) -> WithError<Metrics> {
+    let mut ecpu_usages = Vec::new();
+    let mut pcpu_usages = Vec::new();
+    let mut rs = Metrics::default();
+    let idle = &self.idle_states;
+
+    // channel items are owned by sample, so valid for whole loop
+    for x in sample {
+      if x.group == "CPU Stats" && x.subgroup == CPU_FREQ_CORE_SUBG {
+        if x.channel.contains("ECPU") {
+          ecpu_usages.push(calc_freq(
+            &unsafe { cfio_get_residencies(x.item) },
+            &self.soc.ecpu_freqs,
+            idle,
+          ));
+          continue;
         }
 
-        if x.group == "Energy Model" {
-          match x.channel.as_str() {
-            "GPU Energy" => rs.gpu_power += cfio_watts(x.item, &x.unit, dt)?,
-            // "CPU Energy" for Basic / Max, "DIE_{}_CPU Energy" for Ultra
-            c if c.ends_with("CPU Energy") => rs.cpu_power += cfio_watts(x.item, &x.unit, dt)?,
-            // same pattern next keys: "ANE" for Basic, "ANE0" for Max, "ANE0_{}" for Ultra
-            c if c.starts_with("ANE") => rs.ane_power += cfio_watts(x.item, &x.unit, dt)?,
-            c if c.starts_with("DRAM") => rs.ram_power += cfio_watts(x.item, &x.unit, dt)?,
-            c if c.starts_with("GPU SRAM") => rs.gpu_ram_power += cfio_watts(x.item, &x.unit, dt)?,
-            _ => {}
-          }
+        if x.channel.contains("PCPU") {
+          pcpu_usages.push(calc_freq(
+            &unsafe { cfio_get_residencies(x.item) },
+            &self.soc.pcpu_freqs,
+            idle,
+          ));
+          continue;
         }
       }
 
-      rs.ecpu_usage = calc_freq_final(&ecpu_usages, &self.soc.ecpu_freqs);
-      rs.pcpu_usage = calc_freq_final(&pcpu_usages, &self.soc.pcpu_freqs);
-      results.push(rs);
+      // clusters named like "ECPU", "PCPU", "PCPU1" (two P-clusters on Pro / Max)
+      if x.group == "CPU Stats" && x.subgroup == CPU_FREQ_DICE_SUBG {
+        let freqs =
+          if x.channel.contains("ECPU") { &self.soc.ecpu_freqs } else { &self.soc.pcpu_freqs };
+        let (freq, usage, _) = calc_freq(&unsafe { cfio_get_residencies(x.item) }, freqs, idle);
+        rs.cluster_usage.push((x.channel.clone(), freq, usage));
+        continue;
+      }
+
+      if x.group == "GPU Stats" && x.subgroup == GPU_FREQ_DICE_SUBG && x.channel == "GPUPH" {
+        let items = unsafe { cfio_get_residencies(x.item) };
+        let (freq, usage, idle) = calc_gpu_freq(&items, &self.soc.gpu_freqs, idle);
+        (rs.gpu_usage, rs.gpu_idle_pct) = ((freq, usage), idle);
+      }
+
+      if x.group == energy_group() {
+        add_energy(&mut rs, &x, dt)?;
+      }
+    }
+
+    let (freq, usage, idle) = calc_freq_final(&ecpu_usages, &self.soc.ecpu_freqs);
+    (rs.ecpu_usage, rs.ecpu_idle_pct) = ((freq, usage), idle);
+    let (freq, usage, idle) = calc_freq_final(&pcpu_usages, &self.soc.pcpu_freqs);
+    (rs.pcpu_usage, rs.pcpu_idle_pct) = ((freq, usage), idle);
+    split_cpu_power(&mut rs);
+    Ok(rs)
+  }
+
+  pub fn get_metrics(&mut self, duration: u32) -> WithError<Metrics> {
+    let started = std::time::Instant::now();
+    let measures = self.measures;
+    let mut results: Vec<Metrics> = Vec::with_capacity(measures);
+
+    // do several samples to smooth metrics
+    // see: https://github.com/vladkens/macmon/issues/10
+    for (sample, dt) in self.ior.get_samples(duration as u64, measures) {
+      results.push(self.parse_sample(sample, dt)?);
     }
 
     let mut rs = Metrics::default();
@@ -273,21 +1093,583 @@ impl Sampler {
     rs.pcpu_usage.1 = zero_div(results.iter().map(|x| x.pcpu_usage.1).sum(), measures as _);
     rs.gpu_usage.0 = zero_div(results.iter().map(|x| x.gpu_usage.0).sum(), measures as _);
     rs.gpu_usage.1 = zero_div(results.iter().map(|x| x.gpu_usage.1).sum(), measures as _);
+    rs.ecpu_idle_pct = zero_div(results.iter().map(|x| x.ecpu_idle_pct).sum(), measures as _);
+    rs.pcpu_idle_pct = zero_div(results.iter().map(|x| x.pcpu_idle_pct).sum(), measures as _);
+    rs.gpu_idle_pct = zero_div(results.iter().map(|x| x.gpu_idle_pct).sum(), measures as _);
     rs.cpu_power = zero_div(results.iter().map(|x| x.cpu_power).sum(), measures as _);
+    rs.ecpu_power = avg_some(results.iter().map(|x| x.ecpu_power));
+    rs.pcpu_power = avg_some(results.iter().map(|x| x.pcpu_power));
     rs.gpu_power = zero_div(results.iter().map(|x| x.gpu_power).sum(), measures as _);
     rs.ane_power = zero_div(results.iter().map(|x| x.ane_power).sum(), measures as _);
     rs.ram_power = zero_div(results.iter().map(|x| x.ram_power).sum(), measures as _);
     rs.gpu_ram_power = zero_div(results.iter().map(|x| x.gpu_ram_power).sum(), measures as _);
-    rs.all_power = rs.cpu_power + rs.gpu_power + rs.ane_power;
+    rs.all_power = sum_power(&rs, &self.power_parts);
 
-    rs.memory = self.get_mem()?;
-    rs.temp = self.get_temp()?;
+    let e = self.energy;
+    rs.cpu_energy_mj = sum_energy(&results, e, |x| x.cpu_energy_mj);
+    rs.gpu_energy_mj = sum_energy(&results, e, |x| x.gpu_energy_mj);
+    rs.ane_energy_mj = sum_energy(&results, e, |x| x.ane_energy_mj);
+    rs.ram_energy_mj = sum_energy(&results, e, |x| x.ram_energy_mj);
+    rs.gpu_ram_energy_mj = sum_energy(&results, e, |x| x.gpu_ram_energy_mj);
 
-    rs.sys_power = match self.get_sys_power() {
-      Ok(val) => val.max(rs.all_power),
-      Err(_) => 0.0,
-    };
+    // rough work per watt: fraction of peak throughput (0..1 per cluster) per Watt
+    rs.cpu_efficiency = zero_div(rs.ecpu_usage.1 + rs.pcpu_usage.1, rs.cpu_power);
+    rs.gpu_efficiency = zero_div(rs.gpu_usage.1, rs.gpu_power);
+
+    // clusters come in same order in each sample
+    if let Some(first) = results.first() {
+      for (i, (name, _, _)) in first.cluster_usage.iter().enumerate() {
+        let items = results.iter().filter_map(|x| x.cluster_usage.get(i)).collect::<Vec<_>>();
+        let freq = zero_div(items.iter().map(|x| x.1 as f32).sum(), items.len() as f32);
+        let usage = zero_div(items.iter().map(|x| x.2).sum(), items.len() as f32);
+        rs.cluster_usage.push((name.clone(), freq as u32, usage));
+      }
+    }
+
+    // skip expensive sources (SMC, HID, libc) if not requested
+    if self.filter.has("memory") {
+      rs.memory = self.get_mem()?;
+    }
+
+    if self.filter.has("temp") {
+      if self.temp_count.is_multiple_of(self.temp_every) {
+        self.temp_last = self.get_temp()?;
+      }
+
+      self.temp_count = self.temp_count.wrapping_add(1);
+      rs.temp = self.temp_last;
+    }
+
+    let f = &self.filter;
+    let limits = ["throttling", "cpu_p_limit", "gpu_p_limit", "mem_p_limit"];
+    if limits.iter().any(|x| f.has(x)) {
+      let (throttling, x) = self.get_throttling();
+      (rs.throttling, rs.cpu_p_limit, rs.gpu_p_limit) = (throttling, x.cpu_p_limit, x.gpu_p_limit);
+      rs.mem_p_limit = x.mem_p_limit;
+    }
+
+    if self.filter.has("power_mode") {
+      rs.power_mode = self.get_power_mode();
+    }
+
+    if self.filter.has("sys_power") {
+      let read = self.get_sys_power().ok();
+      (rs.sys_power, rs.sys_power_estimated) = match sys_power_reading(&mut self.sys_power, read) {
+        Some(val) => (val.max(rs.all_power), false),
+        None => {
+          let soc =
+            sum_power(&rs, &[PowerPart::Cpu, PowerPart::Gpu, PowerPart::Ane, PowerPart::Ram]);
+          (soc + sys_baseline_watts(&self.soc.chip_name), true)
+        }
+      };
+    }
+
+    let busy = started.elapsed().saturating_sub(self.ior.slept());
+    rs.collection_ms = busy.as_secs_f32() * 1000.0;
+
+    Ok(rs)
+  }
+}
+
+// MARK: PowerSampler
+
+// lightweight sampler with energy channels only, used for short "instant" power readings
+pub struct PowerSampler {
+  ior: IOReport,
+  power_parts: Vec<PowerPart>,
+}
+
+impl PowerSampler {
+  pub fn new() -> WithError<Self> {
+    let ior = IOReport::new(vec![(energy_group(), None)])?;
+    Ok(PowerSampler { ior, power_parts: DEFAULT_POWER_PARTS.to_vec() })
+  }
+
+  pub fn set_power_parts(&mut self, parts: &[PowerPart]) {
+    self.power_parts = parts.to_vec();
+  }
+
+  pub fn get_power(&mut self, duration: u32) -> WithError<Metrics> {
+    let mut rs = Metrics::default();
+    for (sample, dt) in self.ior.get_samples(duration as u64, 1) {
+      for x in sample {
+        if x.group == energy_group() {
+          add_energy(&mut rs, &x, dt)?;
+        }
+      }
+    }
+
+    split_cpu_power(&mut rs);
+    rs.all_power = sum_power(&rs, &self.power_parts);
+    Ok(rs)
+  }
+
+  // average SoC power since boot from cumulative energy counters, read once without waiting;
+  // uptime includes sleep, so value is lower than draw while awake
+  pub fn get_boot_power(&self) -> WithError<Metrics> {
+    let uptime = libc_uptime_ms()?;
+    if uptime == 0 {
+      return Err("Unknown uptime".into());
+    }
+
+    let mut rs = Metrics::default();
+    for x in self.ior.get_totals() {
+      if x.group == energy_group() {
+        add_energy(&mut rs, &x, uptime)?;
+      }
+    }
 
+    split_cpu_power(&mut rs);
+    rs.all_power = sum_power(&rs, &self.power_parts);
     Ok(rs)
   }
 }
+
+// lifetime average power, None if counters are not available
+pub fn get_boot_power(power_parts: &[PowerPart]) -> Option<f32> {
+  let mut sampler = PowerSampler::new().ok()?;
+  sampler.set_power_parts(power_parts);
+  sampler.get_boot_power().ok().map(|x| x.all_power).filter(|x| *x > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // DVFS tables in MHz as reported by `macmon info`
+  const M1_ECPU: &[u32] = &[600, 972, 1332, 1704, 2064];
+  const M1_PCPU: &[u32] =
+    &[600, 828, 1056, 1284, 1500, 1728, 1956, 2184, 2388, 2592, 2772, 2988, 3096, 3144, 3204];
+  const M2_ECPU: &[u32] = &[912, 1284, 1752, 2004, 2256, 2424];
+  const M3_PCPU: &[u32] = &[
+    696, 1092, 1356, 1596, 1884, 2172, 2424, 2616, 2808, 2988, 3144, 3216, 3288, 3360, 3426, 3480,
+    3528, 3576, 3624, 3696, 3744, 3780, 3816, 3852, 4056,
+  ];
+
+  fn res(items: &[(&str, i64)]) -> Vec<(String, i64)> {
+    items.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+  }
+
+  fn idle() -> Vec<String> {
+    IDLE_STATES.iter().map(|x| x.to_string()).collect()
+  }
+
+  fn approx(a: f32, b: f32) {
+    assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+  }
+
+  fn check(rs: (u32, f32, f32), freq: u32, usage: f32, idle: f32) {
+    assert_eq!(rs.0, freq);
+    approx(rs.1, usage);
+    approx(rs.2, idle);
+  }
+
+  // MARK: Residency fixtures
+
+  // (chip, freqs, per core residency from "CPU Core Performance States", freq, usage, idle)
+  type FreqFixture = (&'static str, &'static [u32], &'static [(&'static str, i64)], u32, f32, f32);
+
+  const FREQ_FIXTURES: &[FreqFixture] = &[
+    (
+      "M1 ECPU",
+      M1_ECPU,
+      &[("IDLE", 600), ("V0P5", 0), ("V1P4", 100), ("V2P3", 0), ("V3P2", 300), ("V4P1", 0)],
+      1521,
+      0.2948,
+      0.6,
+    ),
+    (
+      "M1 PCPU",
+      M1_PCPU,
+      &[
+        ("IDLE", 250),
+        ("V0P15", 0),
+        ("V1P14", 0),
+        ("V2P13", 0),
+        ("V3P12", 0),
+        ("V4P11", 0),
+        ("V5P10", 0),
+        ("V6P9", 0),
+        ("V7P8", 0),
+        ("V8P7", 0),
+        ("V9P6", 0),
+        ("V10P5", 0),
+        ("V11P4", 0),
+        ("V12P3", 0),
+        ("V13P2", 0),
+        ("V14P1", 750),
+      ],
+      3204,
+      0.75,
+      0.25,
+    ),
+    (
+      "M2 ECPU",
+      M2_ECPU,
+      &[
+        ("IDLE", 500),
+        ("DOWN", 0),
+        ("V0P6", 500),
+        ("V1P5", 0),
+        ("V2P4", 0),
+        ("V3P3", 0),
+        ("V4P2", 0),
+        ("V5P1", 0),
+      ],
+      912,
+      0.1881,
+      0.5,
+    ),
+    (
+      "M3 Max PCPU",
+      M3_PCPU,
+      &[
+        ("IDLE", 100),
+        ("DOWN", 400),
+        ("V0P25", 0),
+        ("V1P24", 0),
+        ("V2P23", 0),
+        ("V3P22", 0),
+        ("V4P21", 0),
+        ("V5P20", 0),
+        ("V6P19", 0),
+        ("V7P18", 0),
+        ("V8P17", 0),
+        ("V9P16", 0),
+        ("V10P15", 250),
+        ("V11P14", 0),
+        ("V12P13", 0),
+        ("V13P12", 0),
+        ("V14P11", 0),
+        ("V15P10", 0),
+        ("V16P9", 0),
+        ("V17P8", 0),
+        ("V18P7", 0),
+        ("V19P6", 0),
+        ("V20P5", 0),
+        ("V21P4", 0),
+        ("V22P3", 0),
+        ("V23P2", 0),
+        ("V24P1", 250),
+      ],
+      3600,
+      0.4438,
+      0.5,
+    ),
+  ];
+
+  #[test]
+  fn calc_freq_fixtures() {
+    for (chip, freqs, items, freq, usage, idle_ratio) in FREQ_FIXTURES {
+      let rs = calc_freq(&res(items), freqs, &idle());
+      assert_eq!(rs.0, *freq, "{}", chip);
+      approx(rs.1, *usage);
+      approx(rs.2, *idle_ratio);
+    }
+  }
+
+  #[test]
+  fn calc_freq_final_averages_cores() {
+    // one busy and one fully idle core of M1 E-cluster
+    let busy = calc_freq(&res(FREQ_FIXTURES[0].2), M1_ECPU, &idle());
+    let sleep = calc_freq(&res(&[("IDLE", 1000), ("V0P5", 0)]), M1_ECPU, &idle());
+    check(calc_freq_final(&[busy, sleep], M1_ECPU), 760, 0.1474, 0.8);
+
+    // all cores idle -> lowest freq, not 0
+    check(calc_freq_final(&[sleep, sleep], M1_ECPU), 600, 0.0, 1.0);
+    check(calc_freq_final(&[], M1_ECPU), 600, 0.0, 0.0);
+  }
+
+  #[test]
+  fn calc_freq_throughput() {
+    let freqs = &[1000, 2000];
+
+    // all idle – 0
+    check(calc_freq(&res(&[("IDLE", 100), ("A", 0), ("B", 0)]), freqs, &idle()), 0, 0.0, 1.0);
+    // all at max freq – 1
+    check(calc_freq(&res(&[("IDLE", 0), ("A", 0), ("B", 100)]), freqs, &idle()), 2000, 1.0, 0.0);
+    // half idle & half at max freq – 0.5, same as all at half freq
+    check(calc_freq(&res(&[("IDLE", 50), ("A", 0), ("B", 50)]), freqs, &idle()), 2000, 0.5, 0.5);
+    check(calc_freq(&res(&[("IDLE", 0), ("A", 100), ("B", 0)]), freqs, &idle()), 1000, 0.5, 0.0);
+    // 50 / 50 between states
+    check(calc_freq(&res(&[("IDLE", 0), ("A", 50), ("B", 50)]), freqs, &idle()), 1500, 0.75, 0.0);
+  }
+
+  #[test]
+  fn calc_freq_unexpected_layout() {
+    let items = res(&[("IDLE", 10), ("A", 10), ("B", 10), ("C", 10)]);
+    check(calc_freq(&items, &[], &idle()), 0, 0.0, 0.25);
+    check(calc_freq(&[], &[1000], &idle()), 0, 0.0, 1.0);
+    // states without freq are counted as active time, but add no throughput
+    check(calc_freq(&items, &[1000], &idle()), 333, 0.25, 0.25);
+  }
+
+  // "GPUPH" residency: OFF then one state per non-zero DVFS freq
+  const M1_GPU: &[u32] = &[0, 396, 528, 720, 924, 1128, 1278];
+
+  fn gpu_res(off: i64, active: [i64; 6]) -> Vec<(String, i64)> {
+    let names = ["OFF", "P1", "P2", "P3", "P4", "P5", "P6"];
+    names.iter().zip([off].iter().chain(active.iter())).map(|(k, v)| (k.to_string(), *v)).collect()
+  }
+
+  #[test]
+  fn calc_gpu_freq_idle() {
+    // all time in OFF -> no usage, lowest non-zero freq instead of 0 MHz of OFF state
+    check(calc_gpu_freq(&gpu_res(1000, [0; 6]), M1_GPU, &idle()), 396, 0.0, 1.0);
+    // nothing reported at all is idle too
+    check(calc_gpu_freq(&gpu_res(0, [0; 6]), M1_GPU, &idle()), 396, 0.0, 1.0);
+  }
+
+  #[test]
+  fn calc_gpu_freq_zero_head() {
+    // 0 MHz head is not matched with first active state
+    let rs = calc_gpu_freq(&gpu_res(500, [0, 0, 0, 500, 0, 0]), M1_GPU, &idle());
+    check(rs, 924, 0.3615, 0.5);
+    let rs = calc_gpu_freq(&gpu_res(0, [0, 0, 0, 0, 0, 100]), M1_GPU, &idle());
+    check(rs, 1278, 1.0, 0.0);
+
+    // same result for table without 0 MHz head
+    let rs = calc_gpu_freq(&gpu_res(500, [0, 0, 0, 500, 0, 0]), &M1_GPU[1..], &idle());
+    check(rs, 924, 0.3615, 0.5);
+  }
+
+  #[test]
+  fn calc_gpu_freq_empty_table() {
+    let items = gpu_res(100, [100, 0, 0, 0, 0, 0]);
+    check(calc_gpu_freq(&items, &[0, 0, 0], &idle()), 0, 0.0, 1.0);
+    check(calc_gpu_freq(&items, &[], &idle()), 0, 0.0, 1.0);
+  }
+
+  // MARK: Energy fixtures
+
+  // (chip, "Energy Model" channel -> mJ over 1 s, cpu, gpu, ane, ram, gpu_ram, ecpu, pcpu Watts)
+  type EnergyFixture = (&'static str, &'static [(&'static str, f32)], [f32; 7]);
+
+  const ENERGY_FIXTURES: &[EnergyFixture] = &[
+    (
+      "M1",
+      &[
+        ("ECPU0", 120.0),
+        ("PCPU0", 480.0),
+        ("EACC_CPU", 500.0),
+        ("PACC_CPU", 1500.0),
+        ("CPU Energy", 2500.0),
+        ("GPU Energy", 400.0),
+        ("ANE", 0.0),
+        ("DRAM", 300.0),
+        ("GPU SRAM", 10.0),
+        ("ISP", 50.0),
+      ],
+      [2.5, 0.4, 0.0, 0.3, 0.01, 0.625, 1.875],
+    ),
+    (
+      "M2 Pro",
+      &[
+        ("EACC_CPU", 200.0),
+        ("PACC0_CPU", 1000.0),
+        ("PACC1_CPU", 800.0),
+        ("CPU Energy", 2200.0),
+        ("GPU Energy", 1200.0),
+        ("ANE0", 150.0),
+        ("DRAM0", 500.0),
+        ("GPU SRAM0", 40.0),
+        ("AMCC", 20.0),
+      ],
+      [2.2, 1.2, 0.15, 0.5, 0.04, 0.22, 1.98],
+    ),
+    (
+      "M3 Max",
+      &[
+        ("EACC_CPU", 300.0),
+        ("PACC0_CPU", 2400.0),
+        ("PACC1_CPU", 2300.0),
+        ("CPU Energy", 5000.0),
+        ("GPU Energy", 8000.0),
+        ("ANE0", 0.0),
+        ("DRAM0", 1500.0),
+        ("GPU SRAM0", 120.0),
+      ],
+      [5.0, 8.0, 0.0, 1.5, 0.12, 0.3, 4.7],
+    ),
+    (
+      "M1 Ultra",
+      &[
+        ("DIE_0_EACC_CPU", 100.0),
+        ("DIE_0_PACC0_CPU", 900.0),
+        ("DIE_1_EACC_CPU", 100.0),
+        ("DIE_1_PACC0_CPU", 900.0),
+        ("DIE_0_CPU Energy", 1200.0),
+        ("DIE_1_CPU Energy", 800.0),
+        ("DIE_0_GPU Energy", 3000.0),
+        ("DIE_1_GPU Energy", 2000.0),
+        ("ANE0_0", 100.0),
+        ("ANE0_1", 50.0),
+        ("DRAM0", 700.0),
+        ("DRAM1", 600.0),
+        ("GPU SRAM0", 30.0),
+      ],
+      [2.0, 5.0, 0.15, 1.3, 0.03, 0.2, 1.8],
+    ),
+  ];
+
+  fn parse_energy(channels: &[(&str, f32)], dt: u64) -> Metrics {
+    let mut rs = Metrics::default();
+    for (channel, mj) in channels {
+      add_channel_energy(&mut rs, channel, dt, || Ok(*mj)).unwrap();
+    }
+
+    split_cpu_power(&mut rs);
+    rs
+  }
+
+  #[test]
+  fn add_energy_fixtures() {
+    for (chip, channels, expect) in ENERGY_FIXTURES {
+      let rs = parse_energy(channels, 1000);
+      let [cpu, gpu, ane, ram, gpu_ram, ecpu, pcpu] = *expect;
+      for (name, val, exp) in [
+        ("cpu", rs.cpu_power, cpu),
+        ("gpu", rs.gpu_power, gpu),
+        ("ane", rs.ane_power, ane),
+        ("ram", rs.ram_power, ram),
+        ("gpu_ram", rs.gpu_ram_power, gpu_ram),
+        ("ecpu", rs.ecpu_power.unwrap(), ecpu),
+        ("pcpu", rs.pcpu_power.unwrap(), pcpu),
+      ] {
+        assert!((val - exp).abs() < 1e-3, "{} {}: {} != {}", chip, name, val, exp);
+      }
+
+      // raw energy is total over sample, power is per ms
+      approx(rs.cpu_energy_mj.unwrap(), cpu * 1000.0);
+    }
+  }
+
+  #[test]
+  fn add_energy_skips_unknown_channels_without_reading() {
+    let mut rs = Metrics::default();
+    let rs_read = add_channel_energy(&mut rs, "ISP", 1000, || Err("unit".into()));
+    assert!(rs_read.is_ok());
+    assert_eq!(rs.cpu_power, 0.0);
+    assert!(rs.cpu_energy_mj.is_none());
+  }
+
+  #[test]
+  fn add_energy_without_clusters_has_no_split() {
+    let rs = parse_energy(&[("CPU Energy", 1000.0)], 500);
+    approx(rs.cpu_power, 2.0);
+    assert!(rs.ecpu_power.is_none() && rs.pcpu_power.is_none());
+  }
+
+  #[test]
+  fn ultra_dies_are_summed_once() {
+    // channel names of M2 Ultra "Energy Model", each die reports own CPU / GPU / ANE / DRAM
+    let dies = [
+      ("DIE_0_CPU Energy", 1500.0),
+      ("DIE_1_CPU Energy", 500.0),
+      ("DIE_0_GPU Energy", 4000.0),
+      ("DIE_1_GPU Energy", 3000.0),
+      ("DIE_0_ANE0", 200.0),
+      ("DIE_1_ANE0", 100.0),
+      ("DIE_0_DRAM0", 800.0),
+      ("DIE_1_DRAM0", 700.0),
+    ];
+
+    let rs = parse_energy(&dies, 1000);
+    approx(rs.cpu_power, 2.0);
+    approx(rs.gpu_power, 7.0);
+    approx(rs.ane_power, 0.3);
+    approx(rs.ram_power, 1.5);
+    approx(rs.cpu_energy_mj.unwrap(), 2000.0);
+
+    // one die only (e.g. second die channels missing) is not doubled
+    let rs = parse_energy(&dies[..1], 1000);
+    approx(rs.cpu_power, 1.5);
+    approx(rs.gpu_power, 0.0);
+  }
+
+  // MARK: Metrics filter
+
+  fn names(items: &[&str]) -> Vec<String> {
+    items.iter().map(|x| x.to_string()).collect()
+  }
+
+  #[test]
+  fn metric_names_from_schema() {
+    let known = metric_names();
+    let expected = ["temp", "memory", "ecpu_power", "sys_power_estimated", "cpu_energy_mj"];
+    for name in expected.iter().chain(&["gpu_ram_energy_mj", "mem_p_limit", "collection_ms"]) {
+      assert!(known.iter().any(|x| x == name), "{}", name);
+    }
+  }
+
+  #[test]
+  fn filter_names_and_aliases() {
+    let filter = MetricsFilter::new(&names(&["ram", "temps", "cpu_energy_mj"])).unwrap();
+    assert!(filter.has("memory") && filter.has("temp") && filter.has("cpu_energy_mj"));
+    assert!(!filter.has("gpu_power"));
+
+    // pipe only fields are known only by document which has them
+    assert!(MetricsFilter::new(&names(&["co2_grams"])).is_err());
+    let known = names(&["cpu_power", "co2_grams"]);
+    assert!(MetricsFilter::with_known(&names(&["co2_grams"]), &known).is_ok());
+  }
+
+  #[test]
+  fn filter_apply_keeps_flags_and_other_keys() {
+    let filter = MetricsFilter::new(&names(&["sys_power"])).unwrap();
+    let mut doc = serde_json::to_value(Metrics::default()).unwrap();
+    doc["timestamp"] = serde_json::json!("2024-12-20T10:15:30Z");
+    filter.apply(&mut doc);
+
+    let mut keys = doc.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+    keys.sort();
+    assert_eq!(keys, ["sys_power", "sys_power_estimated", "timestamp"]);
+  }
+
+  #[test]
+  fn session_power_source() {
+    let rs = Metrics { all_power: 8.0, sys_power: 21.5, ..Default::default() };
+    assert_eq!(session_power(&rs), 21.5);
+    // no sensor: SoC power, not estimated system power
+    let rs = Metrics { sys_power_estimated: true, ..rs };
+    assert_eq!(session_power(&rs), 8.0);
+    assert_eq!(session_power(&Metrics { all_power: 8.0, ..Default::default() }), 8.0);
+  }
+
+  #[test]
+  fn sys_power_survives_transient_failures() {
+    let mut state = (0.0, 0);
+    // sensor missing from start: estimate right away
+    assert_eq!(sys_power_reading(&mut state, None), None);
+    assert_eq!(sys_power_reading(&mut state, Some(20.0)), Some(20.0));
+    // single failed read keeps last value
+    assert_eq!(sys_power_reading(&mut state, None), Some(20.0));
+    assert_eq!(sys_power_reading(&mut state, Some(0.0)), Some(20.0));
+    assert_eq!(sys_power_reading(&mut state, None), None);
+    assert_eq!(sys_power_reading(&mut state, Some(22.0)), Some(22.0));
+    assert_eq!(state, (22.0, 0));
+  }
+
+  #[test]
+  fn max_temp_skips_unused_sensors() {
+    assert_eq!(max_temp(&[45.5, 61.0, 52.25]), 61.0);
+    // unpopulated keys read as 0 or garbage
+    assert_eq!(max_temp(&[0.0, -12.0, 48.0, 255.0]), 48.0);
+    assert_eq!(max_temp(&[]), 0.0);
+  }
+
+  #[test]
+  fn strip_die_prefix_cases() {
+    for (channel, expect) in [
+      ("DIE_0_CPU Energy", "CPU Energy"),
+      ("DIE_1_GPU Energy", "GPU Energy"),
+      ("DIE_12_PACC0_CPU", "PACC0_CPU"),
+      ("CPU Energy", "CPU Energy"),
+      ("DIE_X_CPU Energy", "DIE_X_CPU Energy"),
+      ("DIE_", "DIE_"),
+      ("ANE0_1", "ANE0_1"),
+    ] {
+      assert_eq!(strip_die_prefix(channel), expect);
+    }
+  }
+}
@@ -0,0 +1,84 @@
+// Push metrics to OpenTelemetry collector with OTLP/HTTP in JSON encoding (`otlp` feature).
+// Each scalar of pipe document is sent as gauge, chip info as resource attributes.
+
+use serde_json::{json, Value};
+
+use crate::metrics::{flat_fields, Metrics, PowerPart, Sampler};
+use crate::remote::http_post_json;
+use crate::sources::SocInfo;
+use macmon::sink::{run_sinks, MetricSink};
+
+type WithError<T> = Result<T, Box<dyn std::error::Error>>;
+
+fn attr(key: &str, val: Value) -> Value {
+  match val {
+    Value::String(x) => json!({ "key": key, "value": { "stringValue": x } }),
+    x => json!({ "key": key, "value": { "intValue": x.to_string() } }),
+  }
+}
+
+fn resource(soc: &SocInfo) -> Value {
+  let attrs = vec![
+    attr("service.name", json!("macmon")),
+    attr("service.version", json!(env!("CARGO_PKG_VERSION"))),
+    attr("host.arch", json!("arm64")),
+    attr("macmon.chip", json!(soc.chip_name)),
+    attr("macmon.model", json!(soc.mac_model)),
+    attr("macmon.memory_gb", json!(soc.memory_gb)),
+    attr("macmon.ecpu_cores", json!(soc.ecpu_cores)),
+    attr("macmon.pcpu_cores", json!(soc.pcpu_cores)),
+    attr("macmon.gpu_cores", json!(soc.gpu_cores)),
+  ];
+
+  json!({ "attributes": attrs })
+}
+
+// ExportMetricsServiceRequest, int64 values are strings in OTLP JSON
+fn to_otlp(resource: &Value, doc: &Value) -> Value {
+  let ts = chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0).to_string();
+
+  let mut metrics = Vec::new();
+  for (name, val) in flat_fields(doc) {
+    let val = match val.as_f64().or(val.as_bool().map(|x| if x { 1.0 } else { 0.0 })) {
+      Some(x) => x,
+      None => continue,
+    };
+
+    let point = json!({ "timeUnixNano": ts, "asDouble": val });
+    metrics.push(json!({ "name": format!("macmon.{}", name), "gauge": { "dataPoints": [point] } }));
+  }
+
+  let scope = json!({ "name": "macmon", "version": env!("CARGO_PKG_VERSION") });
+  json!({
+    "resourceMetrics": [{
+      "resource": resource,
+      "scopeMetrics": [{ "scope": scope, "metrics": metrics }],
+    }]
+  })
+}
+
+struct OtlpSink {
+  endpoint: String,
+  resource: Value,
+}
+
+impl MetricSink for OtlpSink {
+  fn on_sample(&mut self, metrics: &Metrics, _: &SocInfo) -> WithError<()> {
+    let doc = serde_json::to_value(metrics)?;
+    if let Err(e) = http_post_json(&self.endpoint, &to_otlp(&self.resource, &doc).to_string()) {
+      eprintln!("OTLP export failed: {}", e);
+    }
+
+    Ok(())
+  }
+}
+
+// collector errors are printed and export continues with the next sample
+pub fn run_otlp(endpoint: &str, interval: u32, power_parts: &[PowerPart]) -> WithError<()> {
+  let mut sampler = Sampler::new()?;
+  sampler.set_power_parts(power_parts);
+
+  let sink = OtlpSink { endpoint: endpoint.to_string(), resource: resource(sampler.soc()) };
+  let mut sinks: Vec<Box<dyn MetricSink>> = vec![Box::new(sink)];
+  run_sinks(&mut sampler, interval, 0, &mut sinks)
+}
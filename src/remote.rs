@@ -0,0 +1,69 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::metrics::Metrics;
+
+type WithError<T> = Result<T, Box<dyn std::error::Error>>;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// minimal HTTP/1.0 client, enough to exchange small JSON documents, no TLS
+fn http_request(host: &str, method: &str, path: &str, body: &str) -> WithError<String> {
+  let addr = host.to_socket_addrs()?.next().ok_or(format!("Can't resolve {}", host))?;
+  let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+  stream.set_read_timeout(Some(TIMEOUT))?;
+  stream.set_write_timeout(Some(TIMEOUT))?;
+
+  write!(stream, "{} {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n", method, path, host)?;
+  if !body.is_empty() {
+    write!(stream, "Content-Type: application/json\r\nContent-Length: {}\r\n", body.len())?;
+  }
+  write!(stream, "\r\n{}", body)?;
+
+  let mut res = String::new();
+  stream.read_to_string(&mut res)?;
+
+  let (head, body) = res.split_once("\r\n\r\n").ok_or("Invalid HTTP response")?;
+  let status = head.split(' ').nth(1).unwrap_or("");
+  if !status.starts_with('2') {
+    return Err(format!("HTTP status {}", status).into());
+  }
+
+  Ok(body.to_string())
+}
+
+fn http_get(host: &str, path: &str) -> WithError<String> {
+  http_request(host, "GET", path, "")
+}
+
+// url like "http://host:port/path", port 80 by default
+pub fn http_post_json(url: &str, body: &str) -> WithError<()> {
+  let url = url.strip_prefix("http://").ok_or("Only http:// URLs are supported")?;
+  let (host, path) = match url.find('/') {
+    Some(idx) => (&url[..idx], &url[idx..]),
+    None => (url, "/"),
+  };
+
+  let host = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+  http_request(&host, "POST", path, body)?;
+  Ok(())
+}
+
+// host is "host:port", expects latest pipe document at /json
+pub fn fetch_metrics(host: &str) -> WithError<Metrics> {
+  let host = host.trim_start_matches("http://").trim_end_matches('/');
+  let doc: serde_json::Value = serde_json::from_str(&http_get(host, "/json")?)?;
+
+  // missing keys (e.g. other version or --metrics filter) are kept with default values
+  let mut rs = serde_json::to_value(Metrics::default())?;
+  if let (Some(rs), Some(doc)) = (rs.as_object_mut(), doc.as_object()) {
+    for (k, v) in doc {
+      if rs.contains_key(k) {
+        rs.insert(k.clone(), v.clone());
+      }
+    }
+  }
+
+  Ok(serde_json::from_value(rs)?)
+}
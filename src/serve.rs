@@ -0,0 +1,160 @@
+// HTTP server with recent samples (`serve` feature), std only, one short request per connection.
+// /json – latest pipe document (as expected by `monitor`), /history?window=5m – array of recent
+// documents for Grafana JSON / Infinity datasource, /metrics – Prometheus text format.
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::Value;
+
+use crate::metrics::{flat_fields, Metrics, PowerPart, Sampler};
+use crate::sources::SocInfo;
+use macmon::sink::{run_sinks, MetricSink};
+
+type WithError<T> = Result<T, Box<dyn std::error::Error>>;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// ring buffer of recent pipe documents, oldest first
+struct History {
+  items: VecDeque<(DateTime<Utc>, Value)>,
+  capacity: usize,
+}
+
+impl History {
+  fn push(&mut self, time: DateTime<Utc>, doc: Value) {
+    if self.items.len() >= self.capacity {
+      self.items.pop_front();
+    }
+
+    self.items.push_back((time, doc));
+  }
+
+  fn since(&self, window: Option<Duration>) -> Vec<Value> {
+    let from = window.and_then(|x| chrono::Duration::from_std(x).ok()).map(|x| Utc::now() - x);
+    let items = self.items.iter().filter(|(time, _)| from.is_none_or(|from| *time >= from));
+    items.map(|(_, doc)| doc.clone()).collect()
+  }
+}
+
+fn to_prometheus(doc: &Value) -> String {
+  let mut rs = String::new();
+  for (name, val) in flat_fields(doc) {
+    let val = match val.as_f64().or(val.as_bool().map(|x| if x { 1.0 } else { 0.0 })) {
+      Some(x) => x,
+      None => continue,
+    };
+
+    rs.push_str(&format!("# TYPE macmon_{} gauge\nmacmon_{} {}\n", name, name, val));
+  }
+
+  rs
+}
+
+// "/history?window=5m" -> ("/history", Some("5m"))
+fn parse_path(target: &str) -> (&str, Option<&str>) {
+  let (path, query) = target.split_once('?').unwrap_or((target, ""));
+  let window = query.split('&').find_map(|x| x.strip_prefix("window="));
+  (path, window)
+}
+
+fn route(history: &History, target: &str) -> (&'static str, &'static str, String) {
+  const JSON: &str = "application/json";
+
+  let (path, window) = parse_path(target);
+  match path {
+    "/json" => match history.items.back() {
+      Some((_, doc)) => ("200 OK", JSON, doc.to_string()),
+      None => ("503 Service Unavailable", JSON, "{}".to_string()),
+    },
+    "/history" => match window.map(crate::parse_duration).transpose() {
+      Ok(window) => ("200 OK", JSON, Value::from(history.since(window)).to_string()),
+      Err(e) => ("400 Bad Request", "text/plain", e),
+    },
+    "/metrics" => match history.items.back() {
+      Some((_, doc)) => ("200 OK", "text/plain; version=0.0.4", to_prometheus(doc)),
+      None => ("503 Service Unavailable", "text/plain", String::new()),
+    },
+    _ => ("404 Not Found", "text/plain", "Not found".to_string()),
+  }
+}
+
+fn handle(mut stream: TcpStream, history: &Mutex<History>) -> WithError<()> {
+  stream.set_read_timeout(Some(TIMEOUT))?;
+  stream.set_write_timeout(Some(TIMEOUT))?;
+
+  // only request line is needed, headers and body are ignored
+  let mut buf = [0u8; 4096];
+  let n = stream.read(&mut buf)?;
+  let req = String::from_utf8_lossy(&buf[..n]);
+  let target = match req.split_whitespace().collect::<Vec<_>>()[..] {
+    ["GET", target, ..] => target.to_string(),
+    _ => return Err("Unsupported request".into()),
+  };
+
+  let (status, content_type, body) = route(&history.lock().unwrap(), &target);
+  write!(
+    stream,
+    "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    status,
+    content_type,
+    body.len(),
+    body
+  )?;
+
+  Ok(())
+}
+
+// stores every sample in shared history for the server thread
+struct HistorySink {
+  history: Arc<Mutex<History>>,
+}
+
+impl MetricSink for HistorySink {
+  fn on_sample(&mut self, metrics: &Metrics, _: &SocInfo) -> WithError<()> {
+    let now = Utc::now();
+    let mut doc = serde_json::to_value(metrics)?;
+    if let Some(doc) = doc.as_object_mut() {
+      let ts = now.to_rfc3339_opts(SecondsFormat::Millis, true);
+      doc.insert("timestamp".to_string(), Value::from(ts));
+    }
+
+    self.history.lock().unwrap().push(now, doc);
+    Ok(())
+  }
+}
+
+fn run_server_thread(listener: TcpListener, history: Arc<Mutex<History>>) {
+  std::thread::spawn(move || {
+    for stream in listener.incoming().flatten() {
+      if let Err(e) = handle(stream, &history) {
+        eprintln!("Request failed: {}", e);
+      }
+    }
+  });
+}
+
+// keeps samples for `keep` duration, older ones are dropped
+pub fn run_serve(
+  bind: &str,
+  keep: Duration,
+  interval: u32,
+  power_parts: &[PowerPart],
+) -> WithError<()> {
+  let capacity = (keep.as_millis() as usize / interval.max(1) as usize).max(1);
+  let history = Arc::new(Mutex::new(History { items: VecDeque::new(), capacity }));
+
+  let listener = TcpListener::bind(bind)?;
+  eprintln!("Serving metrics on http://{}", listener.local_addr()?);
+  run_server_thread(listener, history.clone());
+
+  let mut sampler = Sampler::new()?;
+  sampler.set_power_parts(power_parts);
+
+  let mut sinks: Vec<Box<dyn MetricSink>> = vec![Box::new(HistorySink { history })];
+  run_sinks(&mut sampler, interval, 0, &mut sinks)
+}
@@ -0,0 +1,68 @@
+// Sinks decouple sampling from output: one loop collects metrics and passes each sample to all
+// sinks, so embedders can plug own handling (database, UI, etc.) next to JsonLinesSink.
+// `serve` and `otlp` commands run through this loop; `pipe` and TUI keep own loops, as they need
+// signal handling, deadlines, summaries and input events which sinks do not cover.
+//
+// let mut sampler = macmon::metrics::Sampler::new()?;
+// let mut sinks: Vec<Box<dyn MetricSink>> = vec![
+//   Box::new(JsonLinesSink::new(std::io::stdout())),
+//   Box::new(|m: &Metrics, _: &SocInfo| Ok(println!("{:.2}W", m.all_power))),
+// ];
+// run_sinks(&mut sampler, 1000, 10, &mut sinks)?;
+
+use std::io::Write;
+
+use crate::metrics::{Metrics, Sampler};
+use crate::sources::SocInfo;
+
+type WithError<T> = Result<T, Box<dyn std::error::Error>>;
+
+pub trait MetricSink {
+  fn on_sample(&mut self, metrics: &Metrics, soc: &SocInfo) -> WithError<()>;
+}
+
+// closures are sinks too, for quick integrations
+impl<F: FnMut(&Metrics, &SocInfo) -> WithError<()>> MetricSink for F {
+  fn on_sample(&mut self, metrics: &Metrics, soc: &SocInfo) -> WithError<()> {
+    self(metrics, soc)
+  }
+}
+
+// one JSON document per line, flushed after each sample
+pub struct JsonLinesSink<W: Write> {
+  out: W,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+  pub fn new(out: W) -> Self {
+    Self { out }
+  }
+}
+
+impl<W: Write> MetricSink for JsonLinesSink<W> {
+  fn on_sample(&mut self, metrics: &Metrics, _: &SocInfo) -> WithError<()> {
+    writeln!(self.out, "{}", serde_json::to_string(metrics)?)?;
+    Ok(self.out.flush()?)
+  }
+}
+
+// samples every `interval` ms, 0 samples – run until sampler or any sink fails
+pub fn run_sinks(
+  sampler: &mut Sampler,
+  interval: u32,
+  samples: u32,
+  sinks: &mut [Box<dyn MetricSink>],
+) -> WithError<()> {
+  let mut counter = 0u32;
+  loop {
+    let metrics = sampler.get_metrics(interval)?;
+    for sink in sinks.iter_mut() {
+      sink.on_sample(&metrics, sampler.soc())?;
+    }
+
+    counter += 1;
+    if samples > 0 && counter >= samples {
+      return Ok(());
+    }
+  }
+}
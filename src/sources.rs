@@ -18,8 +18,15 @@ use core_foundation::{
     CFDictionaryCreateMutableCopy, CFDictionaryGetCount, CFDictionaryGetKeysAndValues,
     CFDictionaryGetValue, CFDictionaryRef, CFMutableDictionaryRef,
   },
-  number::{kCFNumberSInt32Type, CFNumberCreate, CFNumberRef},
-  string::{kCFStringEncodingUTF8, CFStringCreateWithBytesNoCopy, CFStringGetCString, CFStringRef},
+  number::{kCFNumberSInt32Type, CFNumberCreate, CFNumberGetValue, CFNumberRef},
+  runloop::{
+    kCFRunLoopDefaultMode, CFRunLoopAddSource, CFRunLoopGetCurrent, CFRunLoopRun,
+    CFRunLoopSourceRef,
+  },
+  string::{
+    kCFStringEncodingUTF8, CFStringCreateWithBytesNoCopy, CFStringGetCString, CFStringGetLength,
+    CFStringGetMaximumSizeForEncoding, CFStringRef,
+  },
 };
 
 pub type WithError<T> = Result<T, Box<dyn std::error::Error>>;
@@ -48,17 +55,28 @@ pub fn cfstr(val: &str) -> CFStringRef {
   }
 }
 
-pub fn from_cfstr(val: CFStringRef) -> String {
+/// # Safety
+/// `val` must be a valid CFString (not released yet).
+pub unsafe fn from_cfstr(val: CFStringRef) -> String {
   unsafe {
-    let mut buf = Vec::with_capacity(128);
-    if CFStringGetCString(val, buf.as_mut_ptr(), 128, kCFStringEncodingUTF8) == 0 {
-      panic!("Failed to convert CFString to CString");
+    // max size of UTF-8 bytes for string length, plus NUL terminator
+    let len = CFStringGetLength(val);
+    let size = CFStringGetMaximumSizeForEncoding(len, kCFStringEncodingUTF8) + 1;
+
+    let mut buf = vec![0u8; size as usize];
+    // conversion fails only on broken object, empty name is shown instead of aborting sampler
+    if CFStringGetCString(val, buf.as_mut_ptr() as _, size, kCFStringEncodingUTF8) == 0 {
+      return String::new();
     }
-    std::ffi::CStr::from_ptr(buf.as_ptr()).to_string_lossy().to_string()
+
+    let end = buf.iter().position(|&x| x == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).to_string()
   }
 }
 
-pub fn cfdict_keys(dict: CFDictionaryRef) -> Vec<String> {
+/// # Safety
+/// `dict` must be a valid CFDictionary (not released yet).
+pub unsafe fn cfdict_keys(dict: CFDictionaryRef) -> Vec<String> {
   unsafe {
     let count = CFDictionaryGetCount(dict) as usize;
     let mut keys: Vec<CFStringRef> = Vec::with_capacity(count);
@@ -71,7 +89,9 @@ pub fn cfdict_keys(dict: CFDictionaryRef) -> Vec<String> {
   }
 }
 
-pub fn cfdict_get_val(dict: CFDictionaryRef, key: &str) -> Option<CFTypeRef> {
+/// # Safety
+/// `dict` must be a valid CFDictionary (not released yet).
+pub unsafe fn cfdict_get_val(dict: CFDictionaryRef, key: &str) -> Option<CFTypeRef> {
   unsafe {
     let key = cfstr(key);
     let val = CFDictionaryGetValue(dict, key as _);
@@ -129,21 +149,21 @@ extern "C" {
 fn cfio_get_group(item: CFDictionaryRef) -> String {
   match unsafe { IOReportChannelGetGroup(item) } {
     x if x.is_null() => String::new(),
-    x => from_cfstr(x),
+    x => unsafe { from_cfstr(x) },
   }
 }
 
 fn cfio_get_subgroup(item: CFDictionaryRef) -> String {
   match unsafe { IOReportChannelGetSubGroup(item) } {
     x if x.is_null() => String::new(),
-    x => from_cfstr(x),
+    x => unsafe { from_cfstr(x) },
   }
 }
 
 fn cfio_get_channel(item: CFDictionaryRef) -> String {
   match unsafe { IOReportChannelGetChannelName(item) } {
     x if x.is_null() => String::new(),
-    x => from_cfstr(x),
+    x => unsafe { from_cfstr(x) },
   }
 }
 
@@ -158,7 +178,9 @@ pub fn cfio_get_props(entry: u32, name: String) -> WithError<CFDictionaryRef> {
   }
 }
 
-pub fn cfio_get_residencies(item: CFDictionaryRef) -> Vec<(String, i64)> {
+/// # Safety
+/// `item` must be a channel of IOReport sample which is still alive (see IOReportIterator).
+pub unsafe fn cfio_get_residencies(item: CFDictionaryRef) -> Vec<(String, i64)> {
   let count = unsafe { IOReportStateGetCount(item) };
   let mut res = vec![];
 
@@ -171,17 +193,37 @@ pub fn cfio_get_residencies(item: CFDictionaryRef) -> Vec<(String, i64)> {
   res
 }
 
-pub fn cfio_watts(item: CFDictionaryRef, unit: &String, duration: u64) -> WithError<f32> {
-  let val = unsafe { IOReportSimpleGetIntegerValue(item, 0) } as f32;
-  let val = val / (duration as f32 / 1000.0);
-  match unit.as_str() {
-    "mJ" => Ok(val / 1e3f32),
-    "uJ" => Ok(val / 1e6f32),
-    "nJ" => Ok(val / 1e9f32),
-    _ => Err(format!("Invalid energy unit: {}", unit).into()),
+// raw energy counter value before unit scaling
+/// # Safety
+/// `item` must be a channel of IOReport sample which is still alive (see IOReportIterator).
+pub unsafe fn cfio_energy(item: CFDictionaryRef) -> i64 {
+  unsafe { IOReportSimpleGetIntegerValue(item, 0) }
+}
+
+// energy counter delta in millijoules, single place for unit scaling of all energy channels
+/// # Safety
+/// `item` must be a channel of IOReport sample which is still alive (see IOReportIterator).
+pub unsafe fn cfio_energy_mj(item: CFDictionaryRef, unit: &str) -> WithError<f32> {
+  energy_to_mj(cfio_energy(item) as f32, unit)
+}
+
+fn energy_to_mj(val: f32, unit: &str) -> WithError<f32> {
+  match unit.trim() {
+    "J" => Ok(val * 1e3f32),
+    "mJ" => Ok(val),
+    "uJ" => Ok(val / 1e3f32),
+    "nJ" => Ok(val / 1e6f32),
+    // tick-based and other non-energy units can not be converted without channel specifics
+    _ => Err(format!("Unsupported energy unit: {:?}", unit).into()),
   }
 }
 
+/// # Safety
+/// `item` must be a channel of IOReport sample which is still alive (see IOReportIterator).
+pub unsafe fn cfio_watts(item: CFDictionaryRef, unit: &str, duration: u64) -> WithError<f32> {
+  Ok(cfio_energy_mj(item, unit)? / duration as f32) // mJ / ms = W
+}
+
 // MARK: IOServiceIterator
 
 pub struct IOServiceIterator {
@@ -242,8 +284,8 @@ pub struct IOReportIterator {
 }
 
 impl IOReportIterator {
-  pub fn new(data: CFDictionaryRef) -> Self {
-    let items = cfdict_get_val(data, "IOReportChannels").unwrap() as CFArrayRef;
+  pub(crate) fn new(data: CFDictionaryRef) -> Self {
+    let items = unsafe { cfdict_get_val(data, "IOReportChannels") }.unwrap() as CFArrayRef;
     let items_size = unsafe { CFArrayGetCount(items) } as isize;
     Self { sample: data, items, items_size, index: 0 }
   }
@@ -279,7 +321,7 @@ impl Iterator for IOReportIterator {
     let group = cfio_get_group(item);
     let subgroup = cfio_get_subgroup(item);
     let channel = cfio_get_channel(item);
-    let unit = from_cfstr(unsafe { IOReportChannelGetUnitLabel(item) }).trim().to_string();
+    let unit = unsafe { from_cfstr(IOReportChannelGetUnitLabel(item)) }.trim().to_string();
 
     self.index += 1;
     Some(IOReportIteratorItem { group, subgroup, channel, unit, item })
@@ -288,6 +330,36 @@ impl Iterator for IOReportIterator {
 
 // MARK: RAM
 
+// returns vm stats and page size in bytes
+pub fn libc_vm_stats() -> WithError<(libc::vm_statistics64, u64)> {
+  unsafe {
+    let mut count: u32 = libc::HOST_VM_INFO64_COUNT as _;
+    let mut stats = std::mem::zeroed::<libc::vm_statistics64>();
+
+    // todo: https://github.com/JohnTitor/mach2/issues/34
+    #[allow(deprecated)]
+    let ret_code = libc::host_statistics64(
+      libc::mach_host_self(),
+      libc::HOST_VM_INFO64,
+      &mut stats as *mut _ as *mut _,
+      &mut count,
+    );
+
+    if ret_code != 0 {
+      return Err("Failed to get memory stats".into());
+    }
+
+    let page_size = libc::sysconf(libc::_SC_PAGESIZE) as u64;
+    Ok((stats, page_size))
+  }
+}
+
+// cumulative page counters: (swapins, swapouts, compressions, decompressions)
+pub fn libc_vm_churn() -> WithError<[u64; 4]> {
+  let (stats, _) = libc_vm_stats()?;
+  Ok([stats.swapins, stats.swapouts, stats.compressions, stats.decompressions])
+}
+
 pub fn libc_ram() -> WithError<(u64, u64)> {
   let (mut usage, mut total) = (0u64, 0u64);
 
@@ -308,24 +380,8 @@ pub fn libc_ram() -> WithError<(u64, u64)> {
     }
   }
 
-  unsafe {
-    let mut count: u32 = libc::HOST_VM_INFO64_COUNT as _;
-    let mut stats = std::mem::zeroed::<libc::vm_statistics64>();
-
-    // todo: https://github.com/JohnTitor/mach2/issues/34
-    #[allow(deprecated)]
-    let ret_code = libc::host_statistics64(
-      libc::mach_host_self(),
-      libc::HOST_VM_INFO64,
-      &mut stats as *mut _ as *mut _,
-      &mut count,
-    );
-
-    if ret_code != 0 {
-      return Err("Failed to get memory stats".into());
-    }
-
-    let page_size_kb = libc::sysconf(libc::_SC_PAGESIZE) as u64;
+  {
+    let (stats, page_size_kb) = libc_vm_stats()?;
 
     usage = (0
       + stats.active_count as u64
@@ -342,6 +398,18 @@ pub fn libc_ram() -> WithError<(u64, u64)> {
   Ok((usage, total))
 }
 
+// (wired, compressed, app, cached) in bytes, same as in Activity Monitor
+pub fn libc_ram_breakdown() -> WithError<(u64, u64, u64, u64)> {
+  let (stats, page_size) = libc_vm_stats()?;
+
+  let wired = stats.wire_count as u64 * page_size;
+  let compressed = stats.compressor_page_count as u64 * page_size;
+  let app = (stats.internal_page_count as u64).saturating_sub(stats.purgeable_count as u64);
+  let cached = stats.external_page_count as u64 + stats.purgeable_count as u64;
+
+  Ok((wired, compressed, app * page_size, cached * page_size))
+}
+
 pub fn libc_swap() -> WithError<(u64, u64)> {
   let (mut usage, mut total) = (0u64, 0u64);
 
@@ -370,12 +438,219 @@ pub fn libc_swap() -> WithError<(u64, u64)> {
   Ok((usage, total))
 }
 
-// MARK: SockInfo
+// 1 – normal, 2 – warn, 4 – critical
+pub fn libc_mem_pressure() -> WithError<u8> {
+  let mut level = 0i32;
+
+  unsafe {
+    let name = std::ffi::CString::new("kern.memorystatus_vm_pressure_level").unwrap();
+    let mut size = std::mem::size_of::<i32>();
+    let ret_code = libc::sysctlbyname(
+      name.as_ptr(),
+      &mut level as *mut _ as *mut _,
+      &mut size,
+      std::ptr::null_mut(),
+      0,
+    );
+
+    if ret_code != 0 {
+      return Err("Failed to get memory pressure level".into());
+    }
+  }
+
+  Ok(level as u8)
+}
+
+// time since boot in ms, including sleep
+pub fn libc_uptime_ms() -> WithError<u64> {
+  let mut boot = libc::timeval { tv_sec: 0, tv_usec: 0 };
+
+  unsafe {
+    let name = std::ffi::CString::new("kern.boottime").unwrap();
+    let mut size = std::mem::size_of::<libc::timeval>();
+    let ret_code = libc::sysctlbyname(
+      name.as_ptr(),
+      &mut boot as *mut _ as *mut _,
+      &mut size,
+      std::ptr::null_mut(),
+      0,
+    );
+
+    if ret_code != 0 {
+      return Err("Failed to get boot time".into());
+    }
+  }
+
+  let boot_ms = boot.tv_sec * 1000 + boot.tv_usec as i64 / 1000;
+  let now_ms = chrono::Utc::now().timestamp_millis();
+  Ok((now_ms - boot_ms).max(0) as u64)
+}
+
+// OS build version, e.g. "23E224", changes with every macOS update
+fn libc_os_build() -> Option<String> {
+  let name = std::ffi::CString::new("kern.osversion").unwrap();
+  let mut buf = [0u8; 64];
+  let mut size = buf.len();
+
+  let ret_code = unsafe {
+    libc::sysctlbyname(
+      name.as_ptr(),
+      buf.as_mut_ptr() as *mut _,
+      &mut size,
+      std::ptr::null_mut(),
+      0,
+    )
+  };
+
+  if ret_code != 0 {
+    return None;
+  }
+
+  let val = std::ffi::CStr::from_bytes_until_nul(&buf[..size]).ok()?.to_str().ok()?;
+  Some(val.to_string()).filter(|x| !x.is_empty())
+}
+
+// 1 when process runs under Rosetta, key is missing on Intel Macs
+pub fn libc_proc_translated() -> bool {
+  let mut val = 0i32;
+
+  unsafe {
+    let name = std::ffi::CString::new("sysctl.proc_translated").unwrap();
+    let mut size = std::mem::size_of::<i32>();
+    let ret_code = libc::sysctlbyname(
+      name.as_ptr(),
+      &mut val as *mut _ as *mut _,
+      &mut size,
+      std::ptr::null_mut(),
+      0,
+    );
+
+    ret_code == 0 && val == 1
+  }
+}
+
+// IOReport channels & SMC keys used by macmon exist only on Apple Silicon
+pub fn check_native() -> WithError<()> {
+  if std::env::consts::ARCH != "aarch64" || libc_proc_translated() {
+    return Err("macmon requires Apple Silicon (native arm64 binary, not under Rosetta)".into());
+  }
+
+  Ok(())
+}
+
+// MARK: Processes
 
 #[derive(Debug, Default, Clone)]
+pub struct ProcUsage {
+  pub pid: i32,
+  pub name: String,
+  pub cpu_time_ns: u64, // user + system, cumulative
+  pub energy_nj: u64,   // billed energy, cumulative
+}
+
+// mach absolute time to ns ratio, (numer, denom)
+#[allow(deprecated)]
+fn libc_timebase() -> (u64, u64) {
+  unsafe {
+    let mut tb = std::mem::zeroed::<libc::mach_timebase_info>();
+    libc::mach_timebase_info(&mut tb);
+    (tb.numer.max(1) as u64, tb.denom.max(1) as u64)
+  }
+}
+
+// only processes of current user are available without sudo, others are skipped
+pub fn libc_proc_usage() -> Vec<ProcUsage> {
+  let mut items = Vec::new();
+
+  unsafe {
+    let (numer, denom) = libc_timebase();
+
+    let count = libc::proc_listallpids(std::ptr::null_mut(), 0);
+    if count <= 0 {
+      return items;
+    }
+
+    let mut pids = vec![0i32; count as usize * 2]; // in case new processes spawned
+    let size = (pids.len() * size_of::<i32>()) as i32;
+    let count = libc::proc_listallpids(pids.as_mut_ptr() as _, size).max(0) as usize;
+
+    for &pid in pids.iter().take(count) {
+      let mut ri = std::mem::zeroed::<libc::rusage_info_v4>();
+      let rs = libc::proc_pid_rusage(pid, libc::RUSAGE_INFO_V4, &mut ri as *mut _ as _);
+      if rs != 0 {
+        continue;
+      }
+
+      let mut name = [0u8; 256];
+      let len = libc::proc_name(pid, name.as_mut_ptr() as _, name.len() as u32);
+      let name = String::from_utf8_lossy(&name[..len.max(0) as usize]).to_string();
+
+      let cpu_time_ns = (ri.ri_user_time + ri.ri_system_time) * numer / denom;
+      items.push(ProcUsage { pid, name, cpu_time_ns, energy_nj: ri.ri_billed_energy });
+    }
+  }
+
+  items
+}
+
+// MARK: SockInfo
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ChipGeneration {
+  M1,
+  M2,
+  M3,
+  M4,
+  #[default]
+  Unknown,
+}
+
+impl ChipGeneration {
+  // "Apple M3 Pro" -> M3, generation is "M" + number as separate word
+  pub fn parse(chip_name: &str) -> Self {
+    let num = chip_name
+      .split_whitespace()
+      .filter_map(|x| x.strip_prefix('M'))
+      .find(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit()))
+      .and_then(|x| x.parse::<u32>().ok());
+
+    match num {
+      Some(1) => Self::M1,
+      Some(2) => Self::M2,
+      Some(3) => Self::M3,
+      Some(4) => Self::M4,
+      _ => Self::Unknown,
+    }
+  }
+
+  // DVFS tables have CPU frequencies in Hz before M4, in KHz after
+  pub fn cpu_freq_scale(&self) -> u32 {
+    match self {
+      Self::M1 | Self::M2 | Self::M3 => 1000 * 1000,
+      Self::M4 | Self::Unknown => 1000, // newer chips expected to keep latest scaling
+    }
+  }
+
+  // GPU cores of lowest binned config by chip tier (base, Pro, Max, Ultra), 0 if not known
+  pub fn min_gpu_cores(&self, chip_name: &str) -> u64 {
+    let tier = ["Pro", "Max", "Ultra"].iter().position(|x| chip_name.contains(x));
+    let cores: [u64; 4] = match self {
+      Self::M1 => [7, 14, 24, 48],
+      Self::M2 => [8, 16, 30, 60],
+      Self::M3 => [8, 14, 30, 60],
+      Self::M4 => [8, 16, 32, 0],
+      Self::Unknown => [0; 4],
+    };
+
+    cores[tier.map_or(0, |x| x + 1)]
+  }
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SocInfo {
   pub mac_model: String,
   pub chip_name: String,
+  pub chip_gen: ChipGeneration,
   pub memory_gb: u8,
   pub ecpu_cores: u8,
   pub pcpu_cores: u8,
@@ -383,16 +658,85 @@ pub struct SocInfo {
   pub pcpu_freqs: Vec<u32>,
   pub gpu_cores: u8,
   pub gpu_freqs: Vec<u32>,
+  pub die_count: u8,
+  pub ecpu_clusters: u8,
+  pub pcpu_clusters: u8,
+  pub proc_translated: bool,
 }
 
 impl SocInfo {
+  // clusters are counted from channels of caller subscription (e.g. Sampler one), so it does not
+  // need own IOReport probe; stay 0 if subscription has no "CPU Complex Performance States"
+  pub fn set_clusters(&mut self, ior: &IOReport) {
+    (self.ecpu_clusters, self.pcpu_clusters) = count_clusters(&ior.get_channels());
+  }
+
+  // hardware info never changes, so it is cached to skip slow system_profiler on next runs
   pub fn new() -> WithError<Self> {
-    get_soc_info()
+    let cache = SocCache::path().zip(SocCache::key());
+    if let Some(mut info) = cache.as_ref().and_then(|(path, key)| SocCache::read(path, key)) {
+      info.proc_translated = libc_proc_translated(); // per process, not cached
+      return Ok(info);
+    }
+
+    let info = get_soc_info()?;
+    if let Some((path, key)) = cache {
+      SocCache::write(&path, key, &info);
+    }
+
+    Ok(info)
+  }
+}
+
+// file in ~/.cache/macmon
+pub(crate) fn cache_path(name: &str) -> Option<std::path::PathBuf> {
+  let home = std::env::var("HOME").ok()?;
+  Some(std::path::Path::new(&home).join(".cache/macmon").join(name))
+}
+
+// no cache if OS build is unknown, otherwise it would never be invalidated
+pub(crate) fn cache_key() -> Option<String> {
+  libc_os_build().map(|x| format!("{} {}", env!("CARGO_PKG_VERSION"), x))
+}
+
+// ~/.cache/macmon/soc.json, invalidated by OS update or other macmon version
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SocCache {
+  key: String,
+  soc: SocInfo,
+}
+
+impl SocCache {
+  fn path() -> Option<std::path::PathBuf> {
+    cache_path("soc.json")
+  }
+
+  fn key() -> Option<String> {
+    cache_key()
+  }
+
+  fn read(path: &std::path::Path, key: &str) -> Option<SocInfo> {
+    let cache: SocCache = serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()?;
+    (cache.key == key).then_some(cache.soc)
+  }
+
+  // errors are ignored, next run just calls system_profiler again
+  fn write(path: &std::path::Path, key: String, soc: &SocInfo) {
+    if soc.chip_name.is_empty() || soc.ecpu_cores + soc.pcpu_cores == 0 {
+      return; // do not keep broken detection
+    }
+
+    let _ = std::fs::create_dir_all(path.parent().unwrap());
+    if let Ok(data) = serde_json::to_string(&SocCache { key, soc: soc.clone() }) {
+      let _ = std::fs::write(path, data);
+    }
   }
 }
 
 // dynamic voltage and frequency scaling
-pub fn get_dvfs_mhz(dict: CFDictionaryRef, key: &str) -> (Vec<u32>, Vec<u32>) {
+/// # Safety
+/// `dict` must be a valid CFDictionary, `key` value (if set) must be CFData.
+pub unsafe fn get_dvfs_mhz(dict: CFDictionaryRef, key: &str) -> (Vec<u32>, Vec<u32>) {
   unsafe {
     let obj = cfdict_get_val(dict, key).unwrap() as CFDataRef;
     let obj_len = CFDataGetLength(obj);
@@ -411,6 +755,23 @@ pub fn get_dvfs_mhz(dict: CFDictionaryRef, key: &str) -> (Vec<u32>, Vec<u32>) {
   }
 }
 
+// "low", "high" or "normal" from pmset settings of current power source
+pub fn pmset_power_mode() -> WithError<String> {
+  let out = std::process::Command::new("pmset").arg("-g").output()?;
+  let out = std::str::from_utf8(&out.stdout)?;
+
+  // "powermode" on Macs with High Power Mode (0 – auto, 1 – low, 2 – high), "lowpowermode" on others
+  for line in out.lines() {
+    match line.split_whitespace().collect::<Vec<_>>()[..] {
+      ["powermode", "1"] | ["lowpowermode", "1"] => return Ok("low".to_string()),
+      ["powermode", "2"] => return Ok("high".to_string()),
+      _ => {}
+    }
+  }
+
+  Ok("normal".to_string())
+}
+
 pub fn run_system_profiler() -> WithError<serde_json::Value> {
   // system_profiler -listDataTypes
   let out = std::process::Command::new("system_profiler")
@@ -426,13 +787,54 @@ fn to_mhz(vals: Vec<u32>, scale: u32) -> Vec<u32> {
   vals.iter().map(|x| *x / scale).collect()
 }
 
+// SPDisplaysDataType.*.sppci_cores, summed over all entries (virtualized or future multi-GPU),
+// entries without cores (e.g. external displays on some macOS versions) are skipped
+fn gpu_cores_sum(items: &serde_json::Value) -> u64 {
+  let items = items.as_array().map(|x| x.as_slice()).unwrap_or_default();
+  let cores = items.iter().filter_map(|x| match &x["sppci_cores"] {
+    serde_json::Value::String(x) => x.trim().parse::<u64>().ok(),
+    x => x.as_u64(),
+  });
+
+  cores.sum::<u64>().min(u8::MAX as u64)
+}
+
+// "gpu-core-count" of AGX accelerator service (subclass by GPU family, e.g. AGXAcceleratorG13X)
+fn ioreg_gpu_cores() -> u64 {
+  let services = match IOServiceIterator::new("AGXAccelerator") {
+    Ok(x) => x,
+    Err(_) => return 0,
+  };
+
+  for (entry, name) in services {
+    let props = match cfio_get_props(entry, name) {
+      Ok(x) => x,
+      Err(_) => continue,
+    };
+
+    let mut val = 0i32;
+    if let Some(x) = unsafe { cfdict_get_val(props, "gpu-core-count") } {
+      unsafe { CFNumberGetValue(x as CFNumberRef, kCFNumberSInt32Type, &mut val as *mut i32 as _) };
+    }
+
+    unsafe { CFRelease(props as _) }
+    if val > 0 {
+      return val as u64;
+    }
+  }
+
+  0
+}
+
 pub fn get_soc_info() -> WithError<SocInfo> {
   let out = run_system_profiler()?;
   let mut info = SocInfo::default();
 
-  // SPHardwareDataType.0.chip_type
-  let chip_name =
-    out["SPHardwareDataType"][0]["chip_type"].as_str().unwrap_or("Unknown chip").to_string();
+  // SPHardwareDataType.0.chip_type, Intel Macs have cpu_type instead
+  let chip_name = match out["SPHardwareDataType"][0]["chip_type"].as_str() {
+    Some(x) => x.to_string(),
+    None => return Err("macmon requires Apple Silicon".into()),
+  };
 
   // SPHardwareDataType.0.machine_model
   let mac_model =
@@ -460,22 +862,35 @@ pub fn get_soc_info() -> WithError<SocInfo> {
     (0, 0) // Fallback in case of invalid data
   };
 
-  // SPDisplaysDataType.0.sppci_cores
-  let gpu_cores =
-    out["SPDisplaysDataType"][0]["sppci_cores"].as_str().unwrap_or("0").parse::<u64>().unwrap_or(0);
+  // system_profiler value when valid, then IORegistry, then by chip name (can be lower than real)
+  let mut gpu_cores = gpu_cores_sum(&out["SPDisplaysDataType"]);
+  if gpu_cores == 0 {
+    gpu_cores = ioreg_gpu_cores();
+  }
 
   // Determine scaling based on chip type
-  let before_m4 = chip_name.contains("M1") || chip_name.contains("M2") || chip_name.contains("M3");
-  let cpu_scale: u32 = if before_m4 { 1000 * 1000 } else { 1000 }; // MHz before M4, KHz after
+  let chip_gen = ChipGeneration::parse(&chip_name);
+  if chip_gen == ChipGeneration::Unknown {
+    eprintln!("Unknown chip generation: {}, frequencies may be wrong", chip_name);
+  }
+
+  if gpu_cores == 0 {
+    gpu_cores = chip_gen.min_gpu_cores(&chip_name);
+  }
+
+  let cpu_scale = chip_gen.cpu_freq_scale();
   let gpu_scale: u32 = 1000 * 1000; // MHz
 
   // Assign parsed values to info
   info.chip_name = chip_name;
+  info.chip_gen = chip_gen;
   info.mac_model = mac_model;
   info.memory_gb = mem_gb as u8;
   info.gpu_cores = gpu_cores as u8;
   info.ecpu_cores = ecpu_cores as u8;
   info.pcpu_cores = pcpu_cores as u8;
+  info.die_count = if info.chip_name.contains("Ultra") { 2 } else { 1 }; // Ultra is two Max dies
+  info.proc_translated = libc_proc_translated();
 
   // CPU frequencies
   for (entry, name) in IOServiceIterator::new("AppleARMIODevice")? {
@@ -484,9 +899,9 @@ pub fn get_soc_info() -> WithError<SocInfo> {
       // 1) `strings /usr/bin/powermetrics | grep voltage-states` uses non-sram keys
       //    but their values are zero, so sram used here; it looks valid.
       // 2) sudo powermetrics --samplers cpu_power -i 1000 -n 1 | grep "active residency" | grep "Cluster"
-      info.ecpu_freqs = to_mhz(get_dvfs_mhz(item, "voltage-states1-sram").1, cpu_scale);
-      info.pcpu_freqs = to_mhz(get_dvfs_mhz(item, "voltage-states5-sram").1, cpu_scale);
-      info.gpu_freqs = to_mhz(get_dvfs_mhz(item, "voltage-states9").1, gpu_scale);
+      info.ecpu_freqs = to_mhz(unsafe { get_dvfs_mhz(item, "voltage-states1-sram") }.1, cpu_scale);
+      info.pcpu_freqs = to_mhz(unsafe { get_dvfs_mhz(item, "voltage-states5-sram") }.1, cpu_scale);
+      info.gpu_freqs = to_mhz(unsafe { get_dvfs_mhz(item, "voltage-states9") }.1, gpu_scale);
       unsafe { CFRelease(item as _) }
     }
   }
@@ -498,6 +913,24 @@ pub fn get_soc_info() -> WithError<SocInfo> {
   Ok(info)
 }
 
+// cluster channels named like "ECPU", "PCPU", "PCPU1" (two P-clusters on Pro / Max, x2 on Ultra)
+fn count_clusters(channels: &[(String, String, String)]) -> (u8, u8) {
+  let mut rs = (0, 0);
+  for (group, subgroup, channel) in channels {
+    if group != "CPU Stats" || subgroup != "CPU Complex Performance States" {
+      continue;
+    }
+
+    match channel {
+      x if x.contains("ECPU") => rs.0 += 1,
+      x if x.contains("PCPU") => rs.1 += 1,
+      _ => {}
+    }
+  }
+
+  rs
+}
+
 // MARK: IOReport
 
 unsafe fn cfio_get_chan(items: Vec<(&str, Option<&str>)>) -> WithError<CFMutableDictionaryRef> {
@@ -552,10 +985,35 @@ unsafe fn cfio_get_subs(chan: CFMutableDictionaryRef) -> WithError<IOReportSubsc
   Ok(rs)
 }
 
+// raw sample of subscribed channels with time it was taken, counters in it are cumulative
+pub struct IOReportSnapshot {
+  sample: CFDictionaryRef,
+  at: std::time::Instant,
+}
+
+impl IOReportSnapshot {
+  pub fn at(&self) -> std::time::Instant {
+    self.at
+  }
+
+  // raw values as is, e.g. energy since boot
+  pub fn items(self) -> IOReportIterator {
+    let this = std::mem::ManuallyDrop::new(self); // sample is released by iterator
+    IOReportIterator::new(this.sample)
+  }
+}
+
+impl Drop for IOReportSnapshot {
+  fn drop(&mut self) {
+    unsafe { CFRelease(self.sample as _) }
+  }
+}
+
 pub struct IOReport {
   subs: IOReportSubscriptionRef,
   chan: CFMutableDictionaryRef,
-  prev: Option<(CFDictionaryRef, std::time::Instant)>,
+  prev: Option<IOReportSnapshot>,
+  slept: std::time::Duration, // in last get_samples call
 }
 
 impl IOReport {
@@ -563,52 +1021,65 @@ impl IOReport {
     let chan = unsafe { cfio_get_chan(channels)? };
     let subs = unsafe { cfio_get_subs(chan)? };
 
-    Ok(Self { subs, chan, prev: None })
+    Ok(Self { subs, chan, prev: None, slept: std::time::Duration::ZERO })
+  }
+
+  // raw sample taken now, without sleep, so caller can schedule sampling by own clock
+  pub fn snapshot(&self) -> IOReportSnapshot {
+    let sample = unsafe { IOReportCreateSamples(self.subs, self.chan, null()) };
+    IOReportSnapshot { sample, at: std::time::Instant::now() }
+  }
+
+  // values between two snapshots of the same subscription and elapsed ms (at least 1)
+  pub fn delta(prev: &IOReportSnapshot, next: &IOReportSnapshot) -> (IOReportIterator, u64) {
+    let diff = unsafe { IOReportCreateSamplesDelta(prev.sample, next.sample, null()) };
+    let elapsed = next.at.saturating_duration_since(prev.at).as_millis() as u64;
+    (IOReportIterator::new(diff), elapsed.max(1))
   }
 
   pub fn get_sample(&self, duration: u64) -> IOReportIterator {
-    unsafe {
-      let sample1 = IOReportCreateSamples(self.subs, self.chan, null());
-      std::thread::sleep(std::time::Duration::from_millis(duration));
-      let sample2 = IOReportCreateSamples(self.subs, self.chan, null());
+    let prev = self.snapshot();
+    std::thread::sleep(std::time::Duration::from_millis(duration));
+    Self::delta(&prev, &self.snapshot()).0
+  }
 
-      let sample3 = IOReportCreateSamplesDelta(sample1, sample2, null());
-      CFRelease(sample1 as _);
-      CFRelease(sample2 as _);
-      IOReportIterator::new(sample3)
-    }
+  // (group, subgroup, channel) of subscribed channels, without waiting for delta
+  pub fn get_channels(&self) -> Vec<(String, String, String)> {
+    self.snapshot().items().map(|x| (x.group, x.subgroup, x.channel)).collect()
   }
 
-  fn raw_sample(&self) -> (CFDictionaryRef, std::time::Instant) {
-    (unsafe { IOReportCreateSamples(self.subs, self.chan, null()) }, std::time::Instant::now())
+  // raw counters without delta, for energy channels this is cumulative value since boot
+  pub fn get_totals(&self) -> IOReportIterator {
+    self.snapshot().items()
   }
 
+  // last snapshot is kept between calls, so there are no gaps between consecutive samples
   pub fn get_samples(&mut self, duration: u64, count: usize) -> Vec<(IOReportIterator, u64)> {
     let count = count.max(1).min(32);
     let mut samples: Vec<(IOReportIterator, u64)> = Vec::with_capacity(count);
     let step_msec = duration / count as u64;
 
-    let mut prev = match self.prev {
-      Some(x) => x,
-      None => self.raw_sample(),
-    };
+    let mut prev = self.prev.take().unwrap_or_else(|| self.snapshot());
 
+    self.slept = std::time::Duration::ZERO;
     for _ in 0..count {
+      let started = std::time::Instant::now();
       std::thread::sleep(std::time::Duration::from_millis(step_msec));
+      self.slept += started.elapsed();
 
-      let next = self.raw_sample();
-      let diff = unsafe { IOReportCreateSamplesDelta(prev.0, next.0, null()) };
-      unsafe { CFRelease(prev.0 as _) };
-
-      let elapsed = next.1.duration_since(prev.1).as_millis() as u64;
+      let next = self.snapshot();
+      samples.push(Self::delta(&prev, &next));
       prev = next;
-
-      samples.push((IOReportIterator::new(diff), elapsed.max(1)));
     }
 
     self.prev = Some(prev);
     samples
   }
+
+  // time spent sleeping between samples in last get_samples call
+  pub fn slept(&self) -> std::time::Duration {
+    self.slept
+  }
 }
 
 impl Drop for IOReport {
@@ -616,9 +1087,6 @@ impl Drop for IOReport {
     unsafe {
       CFRelease(self.chan as _);
       CFRelease(self.subs as _);
-      if self.prev.is_some() {
-        CFRelease(self.prev.unwrap().0 as _);
-      }
     }
   }
 }
@@ -658,6 +1126,75 @@ extern "C" {
 
 // MARK: IOHIDSensors
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SensorGroup {
+  Ecpu,
+  Pcpu,
+  Gpu,
+}
+
+// name prefixes of temperature sensors used for CPU / GPU averages, both SMC keys and HID names.
+// Unfortunately, it is not known which keys are responsible for what. Basically in the code that
+// can be found publicly "Tp" is used for CPU and "Tg" for GPU, "Te" are E-cores on chips which
+// have them ("Tp" are P-cores then); HID: "eACC MTR Temp Sensor" – E-cores, "pACC …" – P-cores.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorPrefixes {
+  pub ecpu: Vec<String>,
+  pub pcpu: Vec<String>,
+  pub gpu: Vec<String>,
+}
+
+impl Default for SensorPrefixes {
+  fn default() -> Self {
+    let list = |x: &[&str]| x.iter().map(|x| x.to_string()).collect();
+    Self {
+      ecpu: list(&["Te", "eACC MTR Temp Sensor"]),
+      pcpu: list(&["Tp", "pACC MTR Temp Sensor"]),
+      gpu: list(&["Tg", "GPU MTR Temp Sensor"]),
+    }
+  }
+}
+
+impl SensorPrefixes {
+  // empty lists are replaced with built-in ones, so only one kind can be overridden
+  pub fn new(ecpu: &[String], pcpu: &[String], gpu: &[String]) -> Self {
+    let def = Self::default();
+    let pick = |x: &[String], def: Vec<String>| if x.is_empty() { def } else { x.to_vec() };
+    Self { ecpu: pick(ecpu, def.ecpu), pcpu: pick(pcpu, def.pcpu), gpu: pick(gpu, def.gpu) }
+  }
+
+  pub fn classify(&self, name: &str) -> Option<SensorGroup> {
+    let groups = [
+      (&self.ecpu, SensorGroup::Ecpu),
+      (&self.pcpu, SensorGroup::Pcpu),
+      (&self.gpu, SensorGroup::Gpu),
+    ];
+
+    let matches = |list: &[String]| list.iter().any(|x| name.starts_with(x.as_str()));
+    groups.into_iter().find(|(list, _)| matches(list)).map(|x| x.1)
+  }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum SensorOrder {
+  #[default]
+  Name,
+  Value, // hottest first
+  Kind,  // E-CPU, P-CPU, GPU, other (by prefixes); by name inside group
+}
+
+pub fn sort_sensors(items: &mut [(String, f32)], order: SensorOrder, prefixes: &SensorPrefixes) {
+  match order {
+    SensorOrder::Name => items.sort_by(|a, b| a.0.cmp(&b.0)),
+    SensorOrder::Value => items.sort_by(|a, b| b.1.total_cmp(&a.1)),
+    SensorOrder::Kind => items.sort_by(|a, b| {
+      // None (not averaged) is last
+      let rank = |x: &str| prefixes.classify(x).map_or(3, |x| x as u8);
+      rank(&a.0).cmp(&rank(&b.0)).then_with(|| a.0.cmp(&b.0))
+    }),
+  }
+}
+
 pub struct IOHIDSensors {
   sensors: CFDictionaryRef,
 }
@@ -724,6 +1261,22 @@ impl IOHIDSensors {
       items
     }
   }
+
+  // only sensors which name starts with prefix (if set), in given order
+  pub fn get_metrics_by(
+    &self,
+    order: SensorOrder,
+    prefix: Option<&str>,
+    prefixes: &SensorPrefixes,
+  ) -> Vec<(String, f32)> {
+    let mut items = self.get_metrics();
+    if let Some(prefix) = prefix {
+      items.retain(|(name, _)| name.starts_with(prefix));
+    }
+
+    sort_sensors(&mut items, order, prefixes);
+    items
+  }
 }
 
 impl Drop for IOHIDSensors {
@@ -762,7 +1315,7 @@ pub struct KeyDataVer {
 }
 
 #[repr(C)]
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy, serde::Serialize)]
 pub struct PLimitData {
   pub version: u16,
   pub length: u16,
@@ -800,15 +1353,91 @@ pub struct SensorVal {
   pub data: Vec<u8>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum SmcValue {
+  Float(f32),
+  Unsigned(u64),
+  Signed(i64),
+  Flag(bool),
+  Bytes(Vec<u8>),
+}
+
+impl std::fmt::Display for SmcValue {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SmcValue::Float(x) => write!(f, "{:.2}", x),
+      SmcValue::Unsigned(x) => write!(f, "{}", x),
+      SmcValue::Signed(x) => write!(f, "{}", x),
+      SmcValue::Flag(x) => write!(f, "{}", x),
+      SmcValue::Bytes(x) => {
+        write!(f, "{}", x.iter().map(|x| format!("{:02x}", x)).collect::<Vec<_>>().join(" "))
+      }
+    }
+  }
+}
+
+impl SensorVal {
+  // decode value by its FourCC data type, unknown types returned as raw bytes
+  // `flt ` is little-endian, integers and fixed-point (`fpXY` / `spXY`) are big-endian
+  pub fn decode(&self) -> SmcValue {
+    let d = &self.data;
+    let be = || d.iter().fold(0u64, |acc, x| (acc << 8) + *x as u64);
+
+    // fixed-point: X – integer bits, Y – fraction bits (hex digits), e.g. fp1f, fpe2, sp78
+    // unit is lossy decoded FourCC, so index 3 may be inside multibyte char – raw bytes then
+    let fixed_point = |signed: bool| {
+      let frac = u32::from_str_radix(self.unit.get(3..4)?, 16).ok()?;
+      let raw = u16::from_be_bytes([d[0], d[1]]);
+      let raw = if signed { raw as i16 as f32 } else { raw as f32 };
+      Some(SmcValue::Float(raw / (1u32 << frac) as f32))
+    };
+
+    let rs = match (self.unit.as_str(), d.len()) {
+      ("flt ", 4) => Some(SmcValue::Float(f32::from_le_bytes([d[0], d[1], d[2], d[3]]))),
+      ("ui8 ", 1) | ("ui16", 2) | ("ui32", 4) | ("ui64", 8) => Some(SmcValue::Unsigned(be())),
+      ("si8 ", 1) => Some(SmcValue::Signed(d[0] as i8 as i64)),
+      ("si16", 2) => Some(SmcValue::Signed(i16::from_be_bytes([d[0], d[1]]) as i64)),
+      ("si32", 4) => Some(SmcValue::Signed(i32::from_be_bytes([d[0], d[1], d[2], d[3]]) as i64)),
+      ("flag", 1) => Some(SmcValue::Flag(d[0] != 0)),
+      (u, 2) if u.len() == 4 && u.starts_with("fp") => fixed_point(false),
+      (u, 2) if u.len() == 4 && u.starts_with("sp") => fixed_point(true),
+      _ => None,
+    };
+
+    rs.unwrap_or_else(|| SmcValue::Bytes(d.clone()))
+  }
+
+  pub fn decode_f32(&self) -> WithError<f32> {
+    match self.decode() {
+      SmcValue::Float(x) => Ok(x),
+      SmcValue::Unsigned(x) => Ok(x as f32),
+      SmcValue::Signed(x) => Ok(x as f32),
+      _ => Err(format!("SMC key {} is not a number ({})", self.name, self.unit).into()),
+    }
+  }
+}
+
 // MARK: SMC
 
+// after sleep / wake connection can become invalid, so it is reopened when calls fail in a row
+const SMC_RECONNECT_AFTER: u32 = 3;
+const SMC_MAX_RECONNECTS: u32 = 5; // in a row, without successful call between them
+
 pub struct SMC {
   conn: u32,
   keys: HashMap<u32, KeyInfo>,
+  failures: u32,
+  reconnects: u32,
+  failed_reads: u64,
 }
 
 impl SMC {
   pub fn new() -> WithError<Self> {
+    let conn = Self::open()?;
+    Ok(Self { conn, keys: HashMap::new(), failures: 0, reconnects: 0, failed_reads: 0 })
+  }
+
+  fn open() -> WithError<u32> {
     let mut conn = 0;
 
     for (device, name) in IOServiceIterator::new("AppleSMC")? {
@@ -820,10 +1449,20 @@ impl SMC {
       }
     }
 
-    Ok(Self { conn, keys: HashMap::new() })
+    Ok(conn)
   }
 
-  fn read(&self, input: &KeyData) -> WithError<KeyData> {
+  fn reconnect(&mut self) -> WithError<()> {
+    self.reconnects += 1;
+    self.failures = 0;
+
+    let conn = Self::open()?;
+    unsafe { IOServiceClose(self.conn) };
+    self.conn = conn;
+    Ok(())
+  }
+
+  fn call(&self, input: &KeyData) -> (i32, KeyData) {
     let ival = input as *const _ as _;
     let ilen = size_of::<KeyData>();
     let mut oval = KeyData::default();
@@ -833,11 +1472,29 @@ impl SMC {
       IOConnectCallStructMethod(self.conn, 2, ival, ilen, &mut oval as *mut _ as _, &mut olen)
     };
 
+    (rs, oval)
+  }
+
+  fn read(&mut self, input: &KeyData) -> WithError<KeyData> {
+    let (mut rs, mut oval) = self.call(input);
+
+    // only connection errors counted, missing keys are fine
+    if rs != 0 {
+      self.failures += 1;
+      let retry = self.failures >= SMC_RECONNECT_AFTER && self.reconnects < SMC_MAX_RECONNECTS;
+      if retry && self.reconnect().is_ok() {
+        (rs, oval) = self.call(input);
+      }
+    }
+
     if rs != 0 {
       // println!("{:?}", input);
       return Err(format!("IOConnectCallStructMethod: {}", rs).into());
     }
 
+    self.failures = 0;
+    self.reconnects = 0;
+
     if oval.result == 132 {
       return Err("SMC key not found".into());
     }
@@ -849,7 +1506,7 @@ impl SMC {
     Ok(oval)
   }
 
-  pub fn key_by_index(&self, index: u32) -> WithError<String> {
+  pub fn key_by_index(&mut self, index: u32) -> WithError<String> {
     let ival = KeyData { data8: 8, data32: index, ..Default::default() };
     let oval = self.read(&ival)?;
     Ok(std::str::from_utf8(&oval.key.to_be_bytes()).unwrap().to_string())
@@ -891,9 +1548,39 @@ impl SMC {
     })
   }
 
-  pub fn read_all_keys(&mut self) -> WithError<Vec<String>> {
+  // SMC has no batch read, so it is one call per key; failed keys are skipped and counted
+  pub fn read_f32_each(&mut self, keys: &[String]) -> Vec<f32> {
+    let mut rs = Vec::with_capacity(keys.len());
+    for key in keys {
+      match self.read_val(key).and_then(|x| x.decode_f32()) {
+        Ok(val) => rs.push(val),
+        Err(_) => self.failed_reads += 1,
+      }
+    }
+
+    rs
+  }
+
+  // keys skipped by read_f32_each since start, growing value means sensor is gone or SMC is busy
+  pub fn failed_reads(&self) -> u64 {
+    self.failed_reads
+  }
+
+  // platform power envelope, 0 – no limit applied
+  pub fn read_power_limits(&mut self) -> WithError<PLimitData> {
+    let ival = KeyData { data8: 11, ..Default::default() };
+    let oval = self.read(&ival)?;
+    Ok(oval.p_limit_data)
+  }
+
+  // number of keys reported by SMC, "#KEY"
+  pub fn key_count(&mut self) -> WithError<u32> {
     let val = self.read_val("#KEY")?;
-    let val = u32::from_be_bytes(val.data[0..4].try_into().unwrap());
+    Ok(u32::from_be_bytes(val.data[0..4].try_into()?))
+  }
+
+  pub fn read_all_keys(&mut self) -> WithError<Vec<String>> {
+    let val = self.key_count()?;
 
     let mut keys = Vec::new();
     for i in 0..val {
@@ -918,3 +1605,241 @@ impl Drop for SMC {
     }
   }
 }
+
+// MARK: Display
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+  fn CGMainDisplayID() -> u32;
+  fn CGDisplayIsAsleep(display: u32) -> u32;
+}
+
+pub fn is_display_asleep() -> bool {
+  unsafe { CGDisplayIsAsleep(CGMainDisplayID()) != 0 }
+}
+
+#[repr(C)]
+struct IONotificationPort {
+  _data: [u8; 0],
+  _phantom: PhantomData<(*mut u8, PhantomPinned)>,
+}
+
+type IONotificationPortRef = *mut IONotificationPort;
+type IOServiceInterestCallback = extern "C" fn(*mut c_void, u32, u32, *mut c_void);
+
+// display wrangler sends "will power off" on dim and on sleep, "has powered on" on wake
+const kIOMessageDeviceWillPowerOff: u32 = 0xe0000210;
+const kIOMessageDeviceHasPoweredOn: u32 = 0xe0000230;
+
+#[link(name = "IOKit", kind = "framework")]
+#[rustfmt::skip]
+extern "C" {
+  fn IOServiceGetMatchingService(mainPort: u32, matching: CFDictionaryRef) -> u32;
+  fn IONotificationPortCreate(mainPort: u32) -> IONotificationPortRef;
+  fn IONotificationPortGetRunLoopSource(port: IONotificationPortRef) -> CFRunLoopSourceRef;
+  fn IONotificationPortDestroy(port: IONotificationPortRef);
+  fn IOServiceAddInterestNotification(port: IONotificationPortRef, service: u32, interest: *const i8, callback: IOServiceInterestCallback, refcon: *mut c_void, notification: *mut u32) -> i32;
+}
+
+extern "C" fn on_display_power(refcon: *mut c_void, _: u32, msg: u32, _: *mut c_void) {
+  let cb = unsafe { &mut *(refcon as *mut Box<dyn FnMut(bool)>) };
+  match msg {
+    kIOMessageDeviceWillPowerOff => cb(true),
+    kIOMessageDeviceHasPoweredOn => cb(false),
+    _ => {}
+  }
+}
+
+// blocks current thread in run loop and calls `cb(asleep)` on display power changes (dim counts
+// as asleep), no polling – thread wakes up only on notification
+pub fn watch_display_sleep(cb: impl FnMut(bool) + 'static) -> WithError<()> {
+  let name = std::ffi::CString::new("IODisplayWrangler").unwrap();
+  let interest = std::ffi::CString::new("IOGeneralInterest").unwrap();
+
+  unsafe {
+    let service = IOServiceGetMatchingService(0, IOServiceMatching(name.as_ptr()));
+    if service == 0 {
+      return Err("IODisplayWrangler not found".into());
+    }
+
+    // callback lives until run loop exits, which happens only with thread
+    let mut cb: Box<dyn FnMut(bool)> = Box::new(cb);
+    let port = IONotificationPortCreate(0);
+    let mut notifier = 0u32;
+    let rs = IOServiceAddInterestNotification(
+      port,
+      service,
+      interest.as_ptr(),
+      on_display_power,
+      &mut cb as *mut Box<dyn FnMut(bool)> as _,
+      &mut notifier,
+    );
+    IOObjectRelease(service);
+
+    if rs != 0 {
+      IONotificationPortDestroy(port);
+      return Err(format!("IOServiceAddInterestNotification failed: {}", rs).into());
+    }
+
+    CFRunLoopAddSource(
+      CFRunLoopGetCurrent(),
+      IONotificationPortGetRunLoopSource(port),
+      kCFRunLoopDefaultMode,
+    );
+    CFRunLoopRun();
+
+    IOObjectRelease(notifier);
+    IONotificationPortDestroy(port);
+    Ok(())
+  }
+}
+
+// MARK: Thermal pressure
+// levels: 0 – nominal, 1 – moderate, 2 – heavy, 3 – trapping, 4 – sleeping
+
+extern "C" {
+  fn notify_register_check(name: *const i8, out_token: *mut i32) -> u32;
+  fn notify_get_state(token: i32, state: *mut u64) -> u32;
+  fn notify_cancel(token: i32) -> u32;
+}
+
+pub struct ThermalPressure {
+  token: i32,
+}
+
+impl ThermalPressure {
+  pub fn new() -> WithError<Self> {
+    let name = std::ffi::CString::new("com.apple.system.thermalpressurelevel").unwrap();
+    let mut token = 0;
+    if unsafe { notify_register_check(name.as_ptr(), &mut token) } != 0 {
+      return Err("Failed to register thermal pressure notification".into());
+    }
+
+    Ok(Self { token })
+  }
+
+  pub fn get_level(&self) -> WithError<u64> {
+    let mut level = 0;
+    if unsafe { notify_get_state(self.token, &mut level) } != 0 {
+      return Err("Failed to get thermal pressure level".into());
+    }
+
+    Ok(level)
+  }
+}
+
+impl Drop for ThermalPressure {
+  fn drop(&mut self) {
+    unsafe {
+      notify_cancel(self.token);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn chip_generation_parse() {
+    let cases = [
+      ("Apple M1", ChipGeneration::M1),
+      ("Apple M2 Pro", ChipGeneration::M2),
+      ("Apple M3 Max", ChipGeneration::M3),
+      ("Apple M1 Ultra", ChipGeneration::M1),
+      ("Apple M4", ChipGeneration::M4),
+      ("Apple M9", ChipGeneration::Unknown),
+      ("Apple Max", ChipGeneration::Unknown),
+      ("Intel Core i7", ChipGeneration::Unknown),
+      ("", ChipGeneration::Unknown),
+    ];
+
+    for (name, expected) in cases {
+      assert_eq!(ChipGeneration::parse(name), expected, "{:?}", name);
+    }
+  }
+
+  #[test]
+  fn from_cfstr_long_name() {
+    // buffer is sized by UTF-8 bytes, so long and non-ASCII names are not cut
+    let names = ["PCPU", "GPU Performance States", &"x".repeat(300), "Température ✓"];
+    for name in names {
+      let val = cfstr(name);
+      assert_eq!(unsafe { from_cfstr(val) }, name);
+      unsafe { CFRelease(val as _) };
+    }
+  }
+
+  #[test]
+  fn sort_sensors_by_kind_uses_prefixes() {
+    let items = [("Tg0a", 40.0), ("Tp01", 50.0), ("TB0T", 30.0), ("Te05", 45.0), ("Tp00", 55.0)];
+    let mut items = items.iter().map(|x| (x.0.to_string(), x.1)).collect::<Vec<_>>();
+    let names = |x: &[(String, f32)]| x.iter().map(|x| x.0.clone()).collect::<Vec<_>>();
+
+    sort_sensors(&mut items, SensorOrder::Kind, &SensorPrefixes::default());
+    assert_eq!(names(&items), ["Te05", "Tp00", "Tp01", "Tg0a", "TB0T"]);
+
+    // user prefixes move sensors between groups, e.g. "Tp01" counted as GPU
+    let prefixes = SensorPrefixes::new(&[], &["Tp00".to_string()], &["Tg".into(), "Tp01".into()]);
+    sort_sensors(&mut items, SensorOrder::Kind, &prefixes);
+    assert_eq!(names(&items), ["Te05", "Tp00", "Tg0a", "Tp01", "TB0T"]);
+  }
+
+  #[test]
+  fn count_clusters_by_channel() {
+    let chan = |s: &str, c: &str| ("CPU Stats".to_string(), s.to_string(), c.to_string());
+    let complex = "CPU Complex Performance States";
+    let core = "CPU Core Performance States";
+
+    // M3 Max: one E-cluster, two P-clusters; per core channels are not clusters
+    let channels = [
+      chan(complex, "ECPU"),
+      chan(complex, "PCPU"),
+      chan(complex, "PCPU1"),
+      chan(core, "ECPU0"),
+      chan(core, "PCPU0"),
+      ("Energy Model".to_string(), String::new(), "ECPU".to_string()),
+    ];
+    assert_eq!(count_clusters(&channels), (1, 2));
+
+    // M1 Ultra: clusters of both dies
+    let names =
+      ["DIE_0_ECPU", "DIE_0_PCPU", "DIE_0_PCPU1", "DIE_1_ECPU", "DIE_1_PCPU", "DIE_1_PCPU1"];
+    let channels = names.iter().map(|x| chan(complex, x)).collect::<Vec<_>>();
+    assert_eq!(count_clusters(&channels), (2, 4));
+
+    assert_eq!(count_clusters(&[]), (0, 0));
+  }
+
+  #[test]
+  fn decode_fixed_point() {
+    let val = |unit: &str, data: &[u8]| {
+      SensorVal { name: "TEST".into(), unit: unit.into(), data: data.to_vec() }.decode()
+    };
+
+    assert_eq!(val("fpe2", &[0x01, 0x40]), SmcValue::Float(80.0));
+    assert_eq!(val("sp78", &[0xff, 0x80]), SmcValue::Float(-0.5));
+    // non-ascii FourCC: no panic on char boundary, kept as bytes
+    assert_eq!(val("fp\u{e9}", &[0x01, 0x40]), SmcValue::Bytes(vec![0x01, 0x40]));
+    assert_eq!(val("fp\u{e9}1", &[0x01, 0x40]), SmcValue::Bytes(vec![0x01, 0x40]));
+  }
+
+  #[test]
+  fn energy_units_to_mj() {
+    let cases = [("J", 2.0, 2000.0), ("mJ", 2.0, 2.0), ("uJ", 2000.0, 2.0), ("nJ", 2e6, 2.0)];
+    for (unit, val, expected) in cases {
+      assert_eq!(energy_to_mj(val, unit).unwrap(), expected, "{}", unit);
+    }
+
+    // IOReport labels may be padded
+    assert_eq!(energy_to_mj(5.0, " mJ ").unwrap(), 5.0);
+  }
+
+  #[test]
+  fn energy_units_unknown() {
+    for unit in ["24Mticks", "W", "", "MJ"] {
+      let err = energy_to_mj(1.0, unit).unwrap_err().to_string();
+      assert!(err.contains("Unsupported energy unit"), "{}: {}", unit, err);
+    }
+  }
+}
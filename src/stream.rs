@@ -0,0 +1,57 @@
+// Async adapter over blocking Sampler, for tokio based integrations (`async` feature).
+//
+// use tokio_stream::StreamExt;
+//
+// let mut stream = macmon::stream::metrics_stream(1000).await?;
+// while let Some(metrics) = stream.next().await {
+//   println!("{:.2}W", metrics.all_power);
+// }
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::metrics::{Metrics, Sampler};
+
+pub struct MetricsStream {
+  rx: mpsc::Receiver<Metrics>,
+}
+
+impl Stream for MetricsStream {
+  type Item = Metrics;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.rx.poll_recv(cx)
+  }
+}
+
+// Sampler is not Send, so it is created and used only on blocking thread; sampling stops
+// when stream is dropped. Samples with errors are skipped, same as in TUI.
+pub async fn metrics_stream(interval: u32) -> Result<MetricsStream, String> {
+  let (init_tx, init_rx) = oneshot::channel::<Result<(), String>>();
+  let (tx, rx) = mpsc::channel::<Metrics>(1);
+
+  tokio::task::spawn_blocking(move || {
+    let mut sampler = match Sampler::new() {
+      Ok(sampler) => sampler,
+      Err(e) => return drop(init_tx.send(Err(e.to_string()))),
+    };
+
+    if init_tx.send(Ok(())).is_err() {
+      return;
+    }
+
+    while !tx.is_closed() {
+      if let Ok(metrics) = sampler.get_metrics(interval) {
+        if tx.blocking_send(metrics).is_err() {
+          break;
+        }
+      }
+    }
+  });
+
+  init_rx.await.map_err(|e| e.to_string())??;
+  Ok(MetricsStream { rx })
+}
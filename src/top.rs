@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use crate::metrics::{zero_div, Sampler};
+use crate::sources::{libc_proc_usage, ProcUsage};
+
+type WithError<T> = Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Debug, Default)]
+struct ProcPower {
+  pid: i32,
+  name: String,
+  cpu_share: f32, // from 0.0 to 1.0 of all processes CPU time
+  energy_w: f32,  // from billed energy, Watts
+  cpu_w: f32,     // estimated as cpu_share * cpu_power, Watts
+}
+
+fn calc_proc_power(prev: &[ProcUsage], next: &[ProcUsage], dt: u64, cpu_w: f32) -> Vec<ProcPower> {
+  let prev: HashMap<i32, &ProcUsage> = prev.iter().map(|x| (x.pid, x)).collect();
+
+  let mut items = Vec::new();
+  for x in next {
+    let p = match prev.get(&x.pid) {
+      Some(p) => p,
+      None => continue, // new process, no delta yet
+    };
+
+    let cpu_ns = x.cpu_time_ns.saturating_sub(p.cpu_time_ns);
+    let energy_nj = x.energy_nj.saturating_sub(p.energy_nj);
+    let energy_w = energy_nj as f64 / 1e9 / (dt as f64 / 1000.0);
+    items.push((x, cpu_ns, energy_w as f32));
+  }
+
+  let total_ns = items.iter().map(|x| x.1).sum::<u64>();
+  let mut items = items
+    .into_iter()
+    .map(|(x, cpu_ns, energy_w)| {
+      let cpu_share = zero_div(cpu_ns as f64, total_ns as f64) as f32;
+      ProcPower { pid: x.pid, name: x.name.clone(), cpu_share, energy_w, cpu_w: cpu_share * cpu_w }
+    })
+    .collect::<Vec<_>>();
+
+  items.sort_by(|a, b| b.energy_w.total_cmp(&a.energy_w).then(b.cpu_share.total_cmp(&a.cpu_share)));
+  items
+}
+
+pub fn print_top(count: usize, interval: u32) -> WithError<()> {
+  let mut sampler = Sampler::new()?;
+  let mut prev = libc_proc_usage();
+
+  loop {
+    let started = std::time::Instant::now();
+    let rs = sampler.get_metrics(interval)?;
+    let next = libc_proc_usage();
+    let dt = started.elapsed().as_millis() as u64;
+
+    let items = calc_proc_power(&prev, &next, dt.max(1), rs.cpu_power);
+    prev = next;
+
+    print!("\x1b[2J\x1b[H"); // clear screen
+    let mark = if rs.sys_power_estimated { "~" } else { "" };
+    println!(
+      "CPU {:.2}W  GPU {:.2}W  ANE {:.2}W  All {:.2}W  Total {}{:.2}W\n",
+      rs.cpu_power, rs.gpu_power, rs.ane_power, rs.all_power, mark, rs.sys_power
+    );
+
+    println!("{:>7}  {:<32} {:>6} {:>9} {:>9}", "PID", "NAME", "CPU%", "ENERGY W", "~CPU W");
+    for x in items.iter().take(count) {
+      let name = x.name.chars().take(32).collect::<String>();
+      println!(
+        "{:>7}  {:<32} {:>5.1}% {:>9.3} {:>9.3}",
+        x.pid,
+        name,
+        x.cpu_share * 100.0,
+        x.energy_w,
+        x.cpu_w
+      );
+    }
+  }
+}
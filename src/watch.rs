@@ -0,0 +1,139 @@
+use std::time::Instant;
+
+use crate::metrics::{flat_fields, PowerPart, Sampler};
+use crate::remote::http_post_json;
+
+type WithError<T> = Result<T, Box<dyn std::error::Error>>;
+
+pub const ALERT_EXIT_CODE: i32 = 2;
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+  Gt,
+  Ge,
+  Lt,
+  Le,
+}
+
+// condition like "cpu_temp>90", field is a key of flattened pipe document
+#[derive(Debug, Clone)]
+pub struct Alert {
+  expr: String,
+  field: String,
+  op: Op,
+  threshold: f64,
+}
+
+impl std::str::FromStr for Alert {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    // two-char operators first, so ">=" is not parsed as ">"
+    let ops = [(">=", Op::Ge), ("<=", Op::Le), (">", Op::Gt), ("<", Op::Lt)];
+    for (sym, op) in ops {
+      if let Some((field, val)) = s.split_once(sym) {
+        let threshold =
+          val.trim().parse::<f64>().map_err(|_| format!("Invalid threshold: {}", s))?;
+        let field = match field.trim() {
+          "cpu_temp" => "temp_cpu_temp_avg",
+          "gpu_temp" => "temp_gpu_temp_avg",
+          x => x,
+        };
+
+        return Ok(Self { expr: s.to_string(), field: field.to_string(), op, threshold });
+      }
+    }
+
+    Err(format!("Invalid alert: {} (expected like cpu_temp>90)", s))
+  }
+}
+
+impl Alert {
+  pub fn expr(&self) -> &str {
+    &self.expr
+  }
+
+  // current value of alert field, bools are 1 / 0
+  pub fn value(&self, fields: &[(String, serde_json::Value)]) -> WithError<f64> {
+    let value = fields
+      .iter()
+      .find(|(k, _)| *k == self.field)
+      .and_then(|(_, v)| v.as_f64().or(v.as_bool().map(|x| if x { 1.0 } else { 0.0 })))
+      .ok_or(format!("Unknown metric: {}", self.field))?;
+
+    Ok(value)
+  }
+
+  pub fn check(&self, val: f64) -> bool {
+    match self.op {
+      Op::Gt => val > self.threshold,
+      Op::Ge => val >= self.threshold,
+      Op::Lt => val < self.threshold,
+      Op::Le => val <= self.threshold,
+    }
+  }
+}
+
+fn notify(alert: &Alert, value: f64, doc: &serde_json::Value, webhook: &str) {
+  let body = serde_json::json!({
+    "alert": alert.expr,
+    "value": value,
+    "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+    "metrics": doc,
+  });
+
+  // webhook errors should not stop watching
+  if let Err(e) = http_post_json(webhook, &body.to_string()) {
+    eprintln!("Webhook failed: {}", e);
+  }
+}
+
+// native notification via osascript, runs in background to not block caller
+pub fn desktop_notify(title: &str, msg: &str) {
+  let escape = |x: &str| x.replace('\\', "\\\\").replace('"', "\\\"");
+  let script = format!("display notification \"{}\" with title \"{}\"", escape(msg), escape(title));
+  std::thread::spawn(move || {
+    let _ = std::process::Command::new("osascript").args(["-e", &script]).output();
+  });
+}
+
+// without webhook exits with ALERT_EXIT_CODE on first alert, otherwise keeps watching and
+// notifies once per breach (condition held for `hold` secs), again only after it clears
+pub fn run_watch(
+  alerts: &[Alert],
+  hold: u64,
+  webhook: Option<&str>,
+  interval: u32,
+  power_parts: &[PowerPart],
+) -> WithError<()> {
+  let mut sampler = Sampler::new()?;
+  sampler.set_power_parts(power_parts);
+  let mut since: Vec<Option<Instant>> = vec![None; alerts.len()];
+  let mut fired = vec![false; alerts.len()];
+
+  loop {
+    let doc = serde_json::to_value(sampler.get_metrics(interval)?)?;
+    let fields = flat_fields(&doc);
+
+    for (i, alert) in alerts.iter().enumerate() {
+      let value = alert.value(&fields)?;
+
+      if !alert.check(value) {
+        (since[i], fired[i]) = (None, false);
+        continue;
+      }
+
+      let start = *since[i].get_or_insert_with(Instant::now);
+      if fired[i] || start.elapsed().as_secs() < hold {
+        continue;
+      }
+
+      fired[i] = true;
+      eprintln!("Alert: {} (current: {:.2})", alert.expr, value);
+      match webhook {
+        Some(url) => notify(alert, value, &doc, url),
+        None => std::process::exit(ALERT_EXIT_CODE),
+      }
+    }
+  }
+}